@@ -0,0 +1,340 @@
+use crate::error::{AssemblyError, Span};
+use crate::parser::Field;
+
+/// Largest value that fits in the 15 bits of an A-instruction.
+pub const MAX_CONSTANT: i32 = 32767;
+
+pub struct Code;
+
+impl Code {
+    /// Encodes `dest=comp;jump` from the already encoded fields.
+    pub fn c_instruction(comp: u16, dest: u16, jump: u16) -> u16 {
+        0b111 << 13 | comp << 6 | dest << 3 | jump
+    }
+
+    /// Encodes `@value`.
+    pub fn constant(value: &str, span: &Span) -> Result<u16, AssemblyError> {
+        match value.parse::<i32>() {
+            Ok(num) if (0..=MAX_CONSTANT).contains(&num) => Ok(num as u16),
+            _ => Err(AssemblyError::ConstantOutOfRange { value: value.to_string(), span: span.clone() }),
+        }
+    }
+
+    /// Encodes the dest registers, written in any order.
+    pub fn dest(dest: Option<&Field>) -> Result<u16, AssemblyError> {
+        let Some(d) = dest else { return Ok(0b000) };
+        let unknown = || AssemblyError::UnknownDest { dest: d.text.clone(), span: d.span.clone() };
+
+        let mut bits = 0;
+        for register in d.text.chars() {
+            let bit = match register {
+                'A' => 0b100,
+                'D' => 0b010,
+                'M' => 0b001,
+                _ => return Err(unknown()),
+            };
+            if bits & bit != 0 {
+                return Err(unknown());
+            }
+            bits |= bit;
+        }
+
+        if bits == 0 {
+            return Err(unknown());
+        }
+        Ok(bits)
+    }
+
+    pub fn jump(jump: Option<&Field>) -> Result<u16, AssemblyError> {
+        match jump {
+            None => Ok(0b000),
+            Some(cond) => match &cond.text[..] {
+                "JGT" => Ok(0b001),
+                "JEQ" => Ok(0b010),
+                "JGE" => Ok(0b011),
+                "JLT" => Ok(0b100),
+                "JNE" => Ok(0b101),
+                "JLE" => Ok(0b110),
+                "JMP" => Ok(0b111),
+                _ => Err(AssemblyError::UnknownJump { jump: cond.text.clone(), span: cond.span.clone() }),
+            }
+        }
+    }
+
+    /// Encodes the comp field. The operands of `+`, `&` and `|` may be
+    /// written in either order, e.g. `A+D` is `D+A` and `1+M` is `M+1`.
+    pub fn comp(comp: &Field) -> Result<u16, AssemblyError> {
+        if let Some(bits) = Code::canonical_comp(&comp.text) {
+            return Ok(bits);
+        }
+
+        let swapped = comp.text.find(['+', '&', '|']).and_then(|index| {
+            let (x, y) = (&comp.text[..index], &comp.text[index + 1..]);
+            Code::canonical_comp(&format!("{}{}{}", y, &comp.text[index..index + 1], x))
+        });
+
+        swapped.ok_or_else(|| AssemblyError::UnknownComp { comp: comp.text.clone(), span: comp.span.clone() })
+    }
+
+    fn canonical_comp(comp: &str) -> Option<u16> {
+        let bits = match comp {
+            "0" => 0b0101010,
+            "1" => 0b0111111,
+            "-1" => 0b0111010,
+            "D" => 0b0001100,
+            "A" => 0b0110000,
+            "!D" => 0b0001101,
+            "!A" => 0b0110001,
+            "-D" => 0b0001111,
+            "-A" => 0b0110011,
+            "D+1" => 0b0011111,
+            "A+1" => 0b0110111,
+            "D-1" => 0b0001110,
+            "A-1" => 0b0110010,
+            "D+A" => 0b0000010,
+            "D-A" => 0b0010011,
+            "A-D" => 0b0000111,
+            "D&A" => 0b0000000,
+            "D|A" => 0b0010101,
+            "M" => 0b1110000,
+            "!M" => 0b1110001,
+            "-M" => 0b1110011,
+            "M+1" => 0b1110111,
+            "M-1" => 0b1110010,
+            "D+M" => 0b1000010,
+            "D-M" => 0b1010011,
+            "M-D" => 0b1000111,
+            "D&M" => 0b1000000,
+            "D|M" => 0b1010101,
+            _ => return None,
+        };
+
+        Some(bits)
+    }
+
+    /// Mnemonic of the dest bits, `None` when nothing is stored.
+    pub fn dest_mnemonic(dest: u16) -> Option<&'static str> {
+        match dest & 0b111 {
+            0b001 => Some("M"),
+            0b010 => Some("D"),
+            0b011 => Some("MD"),
+            0b100 => Some("A"),
+            0b101 => Some("AM"),
+            0b110 => Some("AD"),
+            0b111 => Some("AMD"),
+            _ => None,
+        }
+    }
+
+    /// Mnemonic of the jump bits, `None` when there is no jump.
+    pub fn jump_mnemonic(jump: u16) -> Option<&'static str> {
+        match jump & 0b111 {
+            0b001 => Some("JGT"),
+            0b010 => Some("JEQ"),
+            0b011 => Some("JGE"),
+            0b100 => Some("JLT"),
+            0b101 => Some("JNE"),
+            0b110 => Some("JLE"),
+            0b111 => Some("JMP"),
+            _ => None,
+        }
+    }
+
+    /// Mnemonic of the a-bit and ALU control bits, `None` for combinations
+    /// Hack assembly can't express.
+    pub fn comp_mnemonic(comp: u16) -> Option<&'static str> {
+        let mnemonic = match comp & 0b1111111 {
+            0b0101010 => "0",
+            0b0111111 => "1",
+            0b0111010 => "-1",
+            0b0001100 => "D",
+            0b0110000 => "A",
+            0b0001101 => "!D",
+            0b0110001 => "!A",
+            0b0001111 => "-D",
+            0b0110011 => "-A",
+            0b0011111 => "D+1",
+            0b0110111 => "A+1",
+            0b0001110 => "D-1",
+            0b0110010 => "A-1",
+            0b0000010 => "D+A",
+            0b0010011 => "D-A",
+            0b0000111 => "A-D",
+            0b0000000 => "D&A",
+            0b0010101 => "D|A",
+            0b1110000 => "M",
+            0b1110001 => "!M",
+            0b1110011 => "-M",
+            0b1110111 => "M+1",
+            0b1110010 => "M-1",
+            0b1000010 => "D+M",
+            0b1010011 => "D-M",
+            0b1000111 => "M-D",
+            0b1000000 => "D&M",
+            0b1010101 => "D|M",
+            _ => return None,
+        };
+
+        Some(mnemonic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn span() -> Span {
+        Span::new(Rc::from("test.asm"), 1, 1)
+    }
+
+    fn field(text: &str) -> Field {
+        Field { text: text.to_string(), span: span() }
+    }
+
+    #[test]
+    fn test_code_dest() {
+        assert_eq!(Code::dest(None), Ok(0b000));
+        assert_eq!(Code::dest(Some(&field("M"))), Ok(0b001));
+        assert_eq!(Code::dest(Some(&field("D"))), Ok(0b010));
+        assert_eq!(Code::dest(Some(&field("DM"))), Ok(0b011));
+        assert_eq!(Code::dest(Some(&field("A"))), Ok(0b100));
+        assert_eq!(Code::dest(Some(&field("AM"))), Ok(0b101));
+        assert_eq!(Code::dest(Some(&field("AD"))), Ok(0b110));
+        assert_eq!(Code::dest(Some(&field("ADM"))), Ok(0b111));
+        for dest in ["AMD", "MAD", "DAM", "DMA", "MDA"] {
+            assert_eq!(Code::dest(Some(&field(dest))), Ok(0b111));
+        }
+        assert_eq!(Code::dest(Some(&field("MA"))), Ok(0b101));
+        assert_eq!(Code::dest(Some(&field("DA"))), Ok(0b110));
+
+        for dest in ["ELMO", "", "MM", "ADMA"] {
+            assert_eq!(
+                Code::dest(Some(&field(dest))),
+                Err(AssemblyError::UnknownDest { dest: dest.to_string(), span: span() })
+            );
+        }
+    }
+
+    #[test]
+    fn test_code_jump() {
+        assert_eq!(Code::jump(None), Ok(0b000));
+        assert_eq!(Code::jump(Some(&field("JGT"))), Ok(0b001));
+        assert_eq!(Code::jump(Some(&field("JEQ"))), Ok(0b010));
+        assert_eq!(Code::jump(Some(&field("JGE"))), Ok(0b011));
+        assert_eq!(Code::jump(Some(&field("JLT"))), Ok(0b100));
+        assert_eq!(Code::jump(Some(&field("JNE"))), Ok(0b101));
+        assert_eq!(Code::jump(Some(&field("JLE"))), Ok(0b110));
+        assert_eq!(Code::jump(Some(&field("JMP"))), Ok(0b111));
+
+        assert_eq!(
+            Code::jump(Some(&field("ELMO"))),
+            Err(AssemblyError::UnknownJump { jump: "ELMO".to_string(), span: span() })
+        );
+    }
+
+    #[test]
+    fn test_code_comp() {
+        assert_eq!(Code::comp(&field("0")), Ok(0b0101010));
+        assert_eq!(Code::comp(&field("1")), Ok(0b0111111));
+        assert_eq!(Code::comp(&field("-1")), Ok(0b0111010));
+        assert_eq!(Code::comp(&field("D")), Ok(0b0001100));
+        assert_eq!(Code::comp(&field("A")), Ok(0b0110000));
+        assert_eq!(Code::comp(&field("!D")), Ok(0b0001101));
+        assert_eq!(Code::comp(&field("!A")), Ok(0b0110001));
+        assert_eq!(Code::comp(&field("-D")), Ok(0b0001111));
+        assert_eq!(Code::comp(&field("-A")), Ok(0b0110011));
+        assert_eq!(Code::comp(&field("D+1")), Ok(0b0011111));
+        assert_eq!(Code::comp(&field("A+1")), Ok(0b0110111));
+        assert_eq!(Code::comp(&field("D-1")), Ok(0b0001110));
+        assert_eq!(Code::comp(&field("A-1")), Ok(0b0110010));
+        assert_eq!(Code::comp(&field("D+A")), Ok(0b0000010));
+        assert_eq!(Code::comp(&field("D-A")), Ok(0b0010011));
+        assert_eq!(Code::comp(&field("A-D")), Ok(0b0000111));
+        assert_eq!(Code::comp(&field("D&A")), Ok(0b0000000));
+        assert_eq!(Code::comp(&field("D|A")), Ok(0b0010101));
+        assert_eq!(Code::comp(&field("M")), Ok(0b1110000));
+        assert_eq!(Code::comp(&field("!M")), Ok(0b1110001));
+        assert_eq!(Code::comp(&field("-M")), Ok(0b1110011));
+        assert_eq!(Code::comp(&field("M+1")), Ok(0b1110111));
+        assert_eq!(Code::comp(&field("M-1")), Ok(0b1110010));
+        assert_eq!(Code::comp(&field("D+M")), Ok(0b1000010));
+        assert_eq!(Code::comp(&field("D-M")), Ok(0b1010011));
+        assert_eq!(Code::comp(&field("M-D")), Ok(0b1000111));
+        assert_eq!(Code::comp(&field("D&M")), Ok(0b1000000));
+        assert_eq!(Code::comp(&field("D|M")), Ok(0b1010101));
+    }
+
+    #[test]
+    fn test_code_comp_commutative() {
+        assert_eq!(Code::comp(&field("A+D")), Ok(0b0000010));
+        assert_eq!(Code::comp(&field("M+D")), Ok(0b1000010));
+        assert_eq!(Code::comp(&field("A&D")), Ok(0b0000000));
+        assert_eq!(Code::comp(&field("M|D")), Ok(0b1010101));
+        assert_eq!(Code::comp(&field("1+D")), Ok(0b0011111));
+        assert_eq!(Code::comp(&field("1+M")), Ok(0b1110111));
+
+        // subtraction doesn't commute
+        for comp in ["1-D", "A-M", "D+D", "A+M"] {
+            assert_eq!(
+                Code::comp(&field(comp)),
+                Err(AssemblyError::UnknownComp { comp: comp.to_string(), span: span() })
+            );
+        }
+    }
+
+    #[test]
+    fn test_mnemonics_round_trip() {
+        let mut comps = 0;
+        for bits in 0..0b10000000 {
+            if let Some(mnemonic) = Code::comp_mnemonic(bits) {
+                assert_eq!(Code::comp(&field(mnemonic)), Ok(bits));
+                comps += 1;
+            }
+        }
+        assert_eq!(comps, 28);
+
+        assert_eq!(Code::dest_mnemonic(0), None);
+        assert_eq!(Code::jump_mnemonic(0), None);
+        for bits in 1..0b1000 {
+            assert_eq!(Code::dest(Some(&field(Code::dest_mnemonic(bits).unwrap()))), Ok(bits));
+            assert_eq!(Code::jump(Some(&field(Code::jump_mnemonic(bits).unwrap()))), Ok(bits));
+        }
+    }
+
+    #[test]
+    fn test_code_c_instruction() {
+        assert_eq!(Code::c_instruction(0b0001100, 0b000, 0b101), 0b1110001100000101);
+        assert_eq!(Code::c_instruction(0b1110010, 0b101, 0b000), 0b1111110010101000);
+    }
+
+    #[test]
+    fn test_code_constant() {
+        assert_eq!(Code::constant("0", &span()), Ok(0));
+        assert_eq!(Code::constant("21", &span()), Ok(21));
+        assert_eq!(Code::constant("32767", &span()), Ok(32767));
+
+        for value in ["32768", "65535", "99999999999", "-1"] {
+            assert_eq!(
+                Code::constant(value, &span()),
+                Err(AssemblyError::ConstantOutOfRange { value: value.to_string(), span: span() })
+            );
+        }
+    }
+
+    #[test]
+    fn test_code_comp_missing() {
+        assert_eq!(
+            Code::comp(&field("")),
+            Err(AssemblyError::UnknownComp { comp: String::new(), span: span() })
+        );
+    }
+
+    #[test]
+    fn test_code_comp_invalid() {
+        assert_eq!(
+            Code::comp(&field("ELMO")),
+            Err(AssemblyError::UnknownComp { comp: "ELMO".to_string(), span: span() })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::symbol_map;
+use crate::symbol_table::SymbolKind;
+use crate::Program;
+
+/// Renders a JSON object mapping every ROM address to the file, line and
+/// column of its instruction, and every label and variable to its address
+/// and definition. Spans refer to files by their index in `files`.
+pub fn debug_info(program: &Program) -> String {
+    let mut files: Vec<Rc<str>> = Vec::new();
+    let mut file_index = |file: &Rc<str>| match files.iter().position(|known| known == file) {
+        Some(index) => index,
+        None => {
+            files.push(file.clone());
+            files.len() - 1
+        }
+    };
+
+    let mut instructions = String::new();
+    for (address, span) in program.source_map.iter().enumerate() {
+        write!(
+            instructions,
+            "{}\n    {{\"address\": {}, \"file\": {}, \"line\": {}, \"column\": {}}}",
+            if address == 0 { "" } else { "," },
+            address,
+            file_index(&span.file),
+            span.line,
+            span.column
+        )
+        .unwrap();
+    }
+
+    let mut symbols = String::new();
+    let entries = symbol_map::entries(&program.symbols);
+    for (index, entry) in entries.iter().filter(|entry| entry.kind != SymbolKind::Predefined).enumerate() {
+        let span = program.symbols.definition(entry.name).unwrap();
+        write!(
+            symbols,
+            "{}\n    {{\"name\": \"{}\", \"kind\": \"{}\", \"address\": {}, \"file\": {}, \"line\": {}, \"column\": {}}}",
+            if index == 0 { "" } else { "," },
+            entry.name,
+            entry.kind,
+            entry.address,
+            file_index(&span.file),
+            span.line,
+            span.column
+        )
+        .unwrap();
+    }
+
+    let files: Vec<String> = files.iter().map(|file| json_string(file)).collect();
+    format!(
+        "{{\n  \"version\": 1,\n  \"files\": [{}],\n  \"instructions\": [{}\n  ],\n  \"symbols\": [{}\n  ]\n}}\n",
+        files.join(", "),
+        instructions,
+        symbols
+    )
+}
+
+/// Quotes `text` as a JSON string.
+pub(crate) fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_source, Options};
+
+    #[test]
+    fn test_debug_info() {
+        let source = "// count up\n   @i\n   M=0\n(LOOP)\n   @i\n   M=M+1\n   @LOOP\n   0;JMP\n";
+        let program = assemble_source(source, "dir\\count.asm", &Options::default()).unwrap();
+
+        assert_eq!(debug_info(&program), r#"{
+  "version": 1,
+  "files": ["dir\\count.asm"],
+  "instructions": [
+    {"address": 0, "file": 0, "line": 2, "column": 5},
+    {"address": 1, "file": 0, "line": 3, "column": 4},
+    {"address": 2, "file": 0, "line": 5, "column": 5},
+    {"address": 3, "file": 0, "line": 6, "column": 4},
+    {"address": 4, "file": 0, "line": 7, "column": 5},
+    {"address": 5, "file": 0, "line": 8, "column": 4}
+  ],
+  "symbols": [
+    {"name": "LOOP", "kind": "label", "address": 2, "file": 0, "line": 4, "column": 2},
+    {"name": "i", "kind": "variable", "address": 16, "file": 0, "line": 2, "column": 5}
+  ]
+}
+"#);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\n\t"), r#""a\"b\\c\n\u0009""#);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::code::Code;
+use crate::memory::{KBD, SCREEN};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisassemblyOptions {
+    /// Name the targets of jumps `(L_nnnn)` after their ROM address.
+    pub labels: bool,
+    /// Write `@SCREEN` and `@KBD` instead of their addresses.
+    pub symbols: bool,
+}
+
+/// Word that can't be written as Hack assembly, e.g. a C-instruction with
+/// an ALU combination that has no mnemonic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidInstruction {
+    pub address: usize,
+    pub word: u16,
+}
+
+impl fmt::Display for InvalidInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid instruction {:016b} at ROM address {}", self.word, self.address)
+    }
+}
+
+impl Error for InvalidInstruction {}
+
+enum Instruction {
+    A(u16),
+    C { dest: Option<&'static str>, comp: &'static str, jump: Option<&'static str> },
+}
+
+fn decode(word: u16) -> Option<Instruction> {
+    if word & 0x8000 == 0 {
+        return Some(Instruction::A(word));
+    }
+    if word & 0xe000 != 0xe000 {
+        return None;
+    }
+
+    Some(Instruction::C {
+        dest: Code::dest_mnemonic(word >> 3),
+        comp: Code::comp_mnemonic(word >> 6)?,
+        jump: Code::jump_mnemonic(word),
+    })
+}
+
+/// Turns machine code back into Hack assembly, one instruction per line.
+pub fn disassemble(words: &[u16], options: &DisassemblyOptions) -> Result<String, InvalidInstruction> {
+    let instructions = words
+        .iter()
+        .enumerate()
+        .map(|(address, &word)| decode(word).ok_or(InvalidInstruction { address, word }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // an A-instruction followed by a jump loads the jump target
+    let mut targets = BTreeSet::new();
+    if options.labels {
+        for pair in instructions.windows(2) {
+            if let [Instruction::A(value), Instruction::C { jump: Some(_), .. }] = pair {
+                if *value as usize <= words.len() {
+                    targets.insert(*value);
+                }
+            }
+        }
+    }
+
+    let mut asm = String::new();
+    for (address, instruction) in instructions.iter().enumerate() {
+        if targets.contains(&(address as u16)) {
+            writeln!(asm, "(L_{:04})", address).unwrap();
+        }
+
+        match *instruction {
+            Instruction::A(value) if targets.contains(&value) => writeln!(asm, "@L_{:04}", value),
+            Instruction::A(value) if options.symbols && value as usize == SCREEN => writeln!(asm, "@SCREEN"),
+            Instruction::A(value) if options.symbols && value as usize == KBD => writeln!(asm, "@KBD"),
+            Instruction::A(value) => writeln!(asm, "@{}", value),
+            Instruction::C { dest, comp, jump } => {
+                if let Some(dest) = dest {
+                    write!(asm, "{}=", dest).unwrap();
+                }
+                write!(asm, "{}", comp).unwrap();
+                if let Some(jump) = jump {
+                    write!(asm, ";{}", jump).unwrap();
+                }
+                writeln!(asm)
+            }
+        }
+        .unwrap();
+    }
+
+    if targets.contains(&(words.len() as u16)) {
+        writeln!(asm, "(L_{:04})", words.len()).unwrap();
+    }
+
+    Ok(asm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    const MAX: &str = "\
+@R0
+D=M
+@R1
+D=D-M
+@OUTPUT_FIRST
+D;JGT
+@R1
+D=M
+@OUTPUT_D
+0;JMP
+(OUTPUT_FIRST)
+@R0
+D=M
+(OUTPUT_D)
+@R2
+M=D
+@SCREEN
+AM=M-1
+@KBD
+D=!A
+(INFINITE_LOOP)
+@INFINITE_LOOP
+0;JMP
+@END
+0;JMP
+(END)";
+
+    #[test]
+    fn test_disassemble() {
+        let words = assemble(MAX).unwrap().words;
+
+        let asm = disassemble(&words[..4], &DisassemblyOptions::default()).unwrap();
+        assert_eq!(asm, "@0\nD=M\n@1\nD=D-M\n");
+
+        let options = DisassemblyOptions { labels: true, symbols: true };
+        let asm = disassemble(&words, &options).unwrap();
+        assert!(asm.starts_with("@0\nD=M\n@1\nD=D-M\n@L_0010\nD;JGT\n"));
+        assert!(asm.contains("\n(L_0010)\n@0\nD=M\n(L_0012)\n@2\nM=D\n@SCREEN\nAM=M-1\n@KBD\nD=!A\n"));
+        assert!(asm.ends_with("(L_0018)\n@L_0018\n0;JMP\n@L_0022\n0;JMP\n(L_0022)\n"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let words = assemble(MAX).unwrap().words;
+
+        for labels in [false, true] {
+            for symbols in [false, true] {
+                let asm = disassemble(&words, &DisassemblyOptions { labels, symbols }).unwrap();
+                assert_eq!(assemble(&asm).unwrap().words, words);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_instructions() {
+        let options = DisassemblyOptions::default();
+
+        // bits 13 and 14 of a C-instruction must be set
+        assert_eq!(
+            disassemble(&[0, 0b1010101010000111], &options),
+            Err(InvalidInstruction { address: 1, word: 0b1010101010000111 })
+        );
+        // no mnemonic for this ALU combination
+        assert_eq!(
+            disassemble(&[0b1110111101010111], &options),
+            Err(InvalidInstruction { address: 0, word: 0b1110111101010111 })
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use crate::memory::{KBD, RAM_SIZE, ROM_SIZE, SCREEN};
+
+/// Hack CPU with its instruction and data memory. Each call to `step`
+/// executes one instruction, which is one clock cycle on the real hardware.
+pub struct Emulator {
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub ram: Vec<u16>,
+    rom: Vec<u16>,
+    halted: bool,
+}
+
+impl Emulator {
+    /// Loads the program into ROM, the rest of ROM is filled with zeros.
+    pub fn new(program: &[u16]) -> Emulator {
+        let mut rom = vec![0; ROM_SIZE];
+        let size = program.len().min(ROM_SIZE);
+        rom[..size].copy_from_slice(&program[..size]);
+
+        Emulator {
+            a: 0,
+            d: 0,
+            pc: 0,
+            ram: vec![0; RAM_SIZE],
+            rom,
+            halted: false,
+        }
+    }
+
+    /// Clears the registers and RAM, like pressing the reset button.
+    pub fn reset(&mut self) {
+        self.a = 0;
+        self.d = 0;
+        self.pc = 0;
+        self.ram.iter_mut().for_each(|word| *word = 0);
+        self.halted = false;
+    }
+
+    /// Memory-mapped screen, 32 words per row of 512 pixels.
+    pub fn screen(&self) -> &[u16] {
+        &self.ram[SCREEN..KBD]
+    }
+
+    /// Sets the key code the program reads from KBD, 0 when no key is pressed.
+    pub fn set_keyboard(&mut self, key: u16) {
+        self.ram[KBD] = key;
+    }
+
+    /// True once the program jumped to the A-instruction loading its own
+    /// address, the usual `(END) @END 0;JMP` idiom for stopping.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Executes the instruction at PC.
+    pub fn step(&mut self) {
+        let instruction = self.rom[self.pc as usize % ROM_SIZE];
+        let address = self.pc;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+            return;
+        }
+
+        let y = if instruction & 0x1000 != 0 { self.ram[self.a as usize % RAM_SIZE] } else { self.a };
+        let out = alu(self.d, y, instruction >> 6);
+
+        // registers latch at the end of the cycle, so M and the jump both
+        // use the value A had before this instruction
+        let old_a = self.a;
+        if instruction & 0b001000 != 0 {
+            self.write(old_a as usize, out);
+        }
+        if instruction & 0b100000 != 0 {
+            self.a = out;
+        }
+        if instruction & 0b010000 != 0 {
+            self.d = out;
+        }
+
+        let out = out as i16;
+        let jump = (instruction & 0b100 != 0 && out < 0)
+            || (instruction & 0b010 != 0 && out == 0)
+            || (instruction & 0b001 != 0 && out > 0);
+
+        if jump {
+            self.pc = old_a;
+            self.halted = old_a.wrapping_add(1) == address;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+    }
+
+    /// Runs until the program halts or `max_cycles` instructions were
+    /// executed, returns the number of instructions executed.
+    pub fn run(&mut self, max_cycles: usize) -> usize {
+        let mut cycles = 0;
+        while cycles < max_cycles && !self.halted {
+            self.step();
+            cycles += 1;
+        }
+        cycles
+    }
+
+    fn write(&mut self, address: usize, value: u16) {
+        // the keyboard register is read-only
+        if address % RAM_SIZE != KBD {
+            self.ram[address % RAM_SIZE] = value;
+        }
+    }
+}
+
+/// Hack ALU, `control` holds the zx nx zy ny f no bits in its lowest six bits.
+fn alu(x: u16, y: u16, control: u16) -> u16 {
+    let bit = |n: u16| control & (1 << n) != 0;
+
+    let x = if bit(5) { 0 } else { x };
+    let x = if bit(4) { !x } else { x };
+    let y = if bit(3) { 0 } else { y };
+    let y = if bit(2) { !y } else { y };
+    let out = if bit(1) { x.wrapping_add(y) } else { x & y };
+
+    if bit(0) {
+        !out
+    } else {
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    fn emulator(source: &str) -> Emulator {
+        Emulator::new(&assemble(source).unwrap().words)
+    }
+
+    #[test]
+    fn test_alu() {
+        let (x, y) = (0b0101, 0b0011);
+        let cases: [(u16, u16); 18] = [
+            (0b101010, 0),
+            (0b111111, 1),
+            (0b111010, 0xffff),
+            (0b001100, x),
+            (0b110000, y),
+            (0b001101, !x),
+            (0b110001, !y),
+            (0b001111, x.wrapping_neg()),
+            (0b110011, y.wrapping_neg()),
+            (0b011111, x + 1),
+            (0b110111, y + 1),
+            (0b001110, x - 1),
+            (0b110010, y - 1),
+            (0b000010, x + y),
+            (0b010011, x - y),
+            (0b000111, y.wrapping_sub(x)),
+            (0b000000, x & y),
+            (0b010101, x | y),
+        ];
+
+        for (control, expected) in cases {
+            assert_eq!(alu(x, y, control), expected, "control {:06b}", control);
+        }
+    }
+
+    #[test]
+    fn test_max() {
+        let mut emulator = emulator("\
+@R0
+D=M
+@R1
+D=D-M
+@OUTPUT_FIRST
+D;JGT
+@R1
+D=M
+@OUTPUT_D
+0;JMP
+(OUTPUT_FIRST)
+@R0
+D=M
+(OUTPUT_D)
+@R2
+M=D
+(INFINITE_LOOP)
+@INFINITE_LOOP
+0;JMP");
+
+        emulator.ram[0] = 3;
+        emulator.ram[1] = 7;
+        let cycles = emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!(cycles, 14);
+        assert_eq!(emulator.ram[2], 7);
+
+        emulator.reset();
+        emulator.ram[0] = 12;
+        emulator.ram[1] = (-5i16) as u16;
+        emulator.run(1000);
+        assert_eq!(emulator.ram[2], 12);
+    }
+
+    #[test]
+    fn test_mult_cycle_limit() {
+        let mut emulator = emulator("\
+@R2
+M=0
+(LOOP)
+@R1
+D=M
+@END
+D;JLE
+@R0
+D=M
+@R2
+M=D+M
+@R1
+M=M-1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP");
+
+        emulator.ram[0] = 6;
+        emulator.ram[1] = 7;
+        assert_eq!(emulator.run(20), 20);
+        assert!(!emulator.is_halted());
+
+        emulator.run(1000);
+        assert!(emulator.is_halted());
+        assert_eq!(emulator.ram[2], 42);
+    }
+
+    #[test]
+    fn test_memory_uses_old_a() {
+        let mut emulator = emulator("@10\nD=A\n@5\nM=D\nAM=M+1\nM=D\n");
+        emulator.run(6);
+        assert_eq!((emulator.ram[5], emulator.a, emulator.ram[11]), (11, 11, 10));
+    }
+
+    #[test]
+    fn test_memory_maps() {
+        let mut emulator = emulator("@KBD\nD=M\n@SCREEN\nM=D\n@KBD\nM=-1\n");
+        emulator.set_keyboard(65);
+        emulator.run(6);
+        assert_eq!(emulator.screen()[0], 65);
+        assert_eq!(emulator.ram[KBD], 65);
+    }
+}
@@ -0,0 +1,296 @@
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::memory::{KBD, ROM_SIZE};
+
+/// Location in the source file, both line and column are 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub column: usize,
+    /// Set for code from a macro body, the macro call it was expanded from.
+    pub expansion: Option<Rc<Expansion>>,
+    /// Set for code from an included file, the `.include` directive.
+    pub included_from: Option<Rc<Span>>,
+}
+
+/// Macro call that produced the code of a span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub call: Span,
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: usize, column: usize) -> Span {
+        Span { file, line, column, expansion: None, included_from: None }
+    }
+
+    /// Location of the outermost macro call, or the span itself outside of macros.
+    pub fn call_site(&self) -> &Span {
+        match &self.expansion {
+            Some(expansion) => expansion.call.call_site(),
+            None => self,
+        }
+    }
+
+    /// Location in the file being assembled that produced this span,
+    /// following macro calls and includes.
+    pub fn origin(&self) -> &Span {
+        match (&self.expansion, &self.included_from) {
+            (Some(expansion), _) => expansion.call.origin(),
+            (None, Some(include)) => include.origin(),
+            (None, None) => self,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyError {
+    UnknownComp { comp: String, span: Span },
+    UnknownDest { dest: String, span: Span },
+    UnknownJump { jump: String, span: Span },
+    MalformedLabel { label: String, span: Span },
+    MalformedConstant { definition: String, span: Span },
+    MalformedMacro { definition: String, span: Span },
+    UnterminatedMacro { name: String, span: Span },
+    DuplicateMacro { name: String, span: Span },
+    MacroArguments { name: String, expected: usize, found: usize, span: Span },
+    MacroRecursion { name: String, span: Span },
+    MalformedInclude { directive: String, span: Span },
+    IncludeFailed { path: String, reason: String, span: Span },
+    IncludeCycle { path: String, span: Span },
+    BadAInstruction { operand: String, span: Span },
+    ConstantOutOfRange { value: String, span: Span },
+    UndefinedSymbol { symbol: String, span: Span },
+    DivisionByZero { span: Span },
+    DuplicateLabel { label: String, span: Span },
+    DuplicateConstant { constant: String, span: Span },
+    RomOverflow { size: usize, span: Span },
+    RamOverflow { variable: String, address: i32, span: Span },
+}
+
+impl AssemblyError {
+    pub fn span(&self) -> &Span {
+        match self {
+            AssemblyError::UnknownComp { span, .. }
+            | AssemblyError::UnknownDest { span, .. }
+            | AssemblyError::UnknownJump { span, .. }
+            | AssemblyError::MalformedLabel { span, .. }
+            | AssemblyError::MalformedConstant { span, .. }
+            | AssemblyError::MalformedMacro { span, .. }
+            | AssemblyError::UnterminatedMacro { span, .. }
+            | AssemblyError::DuplicateMacro { span, .. }
+            | AssemblyError::MacroArguments { span, .. }
+            | AssemblyError::MacroRecursion { span, .. }
+            | AssemblyError::MalformedInclude { span, .. }
+            | AssemblyError::IncludeFailed { span, .. }
+            | AssemblyError::IncludeCycle { span, .. }
+            | AssemblyError::BadAInstruction { span, .. }
+            | AssemblyError::ConstantOutOfRange { span, .. }
+            | AssemblyError::UndefinedSymbol { span, .. }
+            | AssemblyError::DivisionByZero { span }
+            | AssemblyError::DuplicateLabel { span, .. }
+            | AssemblyError::DuplicateConstant { span, .. }
+            | AssemblyError::RomOverflow { span, .. }
+            | AssemblyError::RamOverflow { span, .. } => span,
+        }
+    }
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblyError::UnknownComp { comp, .. } if comp.is_empty() => write!(f, "missing comp"),
+            AssemblyError::UnknownComp { comp, .. } => write!(f, "unknown comp `{}`", comp),
+            AssemblyError::UnknownDest { dest, .. } => write!(f, "unknown dest `{}`", dest),
+            AssemblyError::UnknownJump { jump, .. } => write!(f, "unknown jump condition `{}`", jump),
+            AssemblyError::MalformedLabel { label, .. } => write!(f, "malformed label `{}`", label),
+            AssemblyError::MalformedConstant { definition, .. } => {
+                write!(f, "malformed constant definition `{}`", definition)
+            }
+            AssemblyError::MalformedMacro { definition, .. } => {
+                write!(f, "malformed macro definition `{}`", definition)
+            }
+            AssemblyError::UnterminatedMacro { name, .. } => write!(f, "macro `{}` has no `.endm`", name),
+            AssemblyError::DuplicateMacro { name, .. } => write!(f, "macro `{}` is already defined", name),
+            AssemblyError::MacroArguments { name, expected, found, .. } => {
+                write!(f, "macro `{}` expects {} arguments, found {}", name, expected, found)
+            }
+            AssemblyError::MacroRecursion { name, .. } => {
+                write!(f, "macro `{}` calls itself", name)
+            }
+            AssemblyError::MalformedInclude { directive, .. } => {
+                write!(f, "malformed include `{}`, expected `.include \"file\"`", directive)
+            }
+            AssemblyError::IncludeFailed { path, reason, .. } => write!(f, "can't include `{}`: {}", path, reason),
+            AssemblyError::IncludeCycle { path, .. } => write!(f, "`{}` is already being included", path),
+            AssemblyError::BadAInstruction { operand, .. } => {
+                write!(f, "bad A-instruction operand `{}`", operand)
+            }
+            AssemblyError::ConstantOutOfRange { value, .. } => {
+                write!(f, "constant `{}` is out of range, expected 0..=32767", value)
+            }
+            AssemblyError::UndefinedSymbol { symbol, .. } => {
+                write!(f, "symbol `{}` is not defined before it is used in an expression", symbol)
+            }
+            AssemblyError::DivisionByZero { .. } => write!(f, "division by zero"),
+            AssemblyError::DuplicateLabel { label, .. } => write!(f, "duplicate label `{}`", label),
+            AssemblyError::DuplicateConstant { constant, .. } => {
+                write!(f, "constant `{}` is already defined", constant)
+            }
+            AssemblyError::RomOverflow { size, .. } => {
+                write!(f, "program has {} instructions, but ROM only holds {}", size, ROM_SIZE)
+            }
+            AssemblyError::RamOverflow { variable, address, .. } => {
+                let region = if *address >= KBD as i32 { "KBD" } else { "SCREEN" };
+                write!(f, "variable `{}` would be allocated at {}, inside {} memory", variable, address, region)
+            }
+        }
+    }
+}
+
+impl Error for AssemblyError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    RamBudgetExceeded { variable: String, budget: i32, span: Span },
+    /// Issued in lint mode for comp or dest fields written differently
+    /// from the mnemonic in the Hack specification.
+    NonCanonical { found: String, canonical: &'static str, span: Span },
+}
+
+impl Warning {
+    pub fn span(&self) -> &Span {
+        match self {
+            Warning::RamBudgetExceeded { span, .. } => span,
+            Warning::NonCanonical { span, .. } => span,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::RamBudgetExceeded { variable, budget, .. } => {
+                write!(f, "variable `{}` exceeds the RAM budget of {} words", variable, budget)
+            }
+            Warning::NonCanonical { found, canonical, .. } => {
+                write!(f, "`{}` is usually written `{}`", found, canonical)
+            }
+        }
+    }
+}
+
+/// Malformed line in a machine code file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for FormatError {}
+
+/// Error or warning reported while assembling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    Error(AssemblyError),
+    Warning(Warning),
+}
+
+impl Diagnostic {
+    pub fn span(&self) -> &Span {
+        match self {
+            Diagnostic::Error(err) => err.span(),
+            Diagnostic::Warning(warning) => warning.span(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Diagnostic::Error(_))
+    }
+}
+
+/// Formats as `file:line:col: error: message`, followed by a note for
+/// each macro call the code was expanded from and each file it was
+/// included from.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Error(err) => write!(f, "{}: error: {}", err.span(), err)?,
+            Diagnostic::Warning(warning) => write!(f, "{}: warning: {}", warning.span(), warning)?,
+        }
+
+        let mut span = self.span();
+        loop {
+            if let Some(expansion) = &span.expansion {
+                write!(f, "\n{}: note: in expansion of macro `{}`", expansion.call, expansion.name)?;
+                span = &expansion.call;
+            } else if let Some(include) = &span.included_from {
+                write!(f, "\n{}: note: `{}` included from here", include, span.file)?;
+                span = include;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Diagnostics of a source file that failed to assemble.
+#[derive(Debug)]
+pub struct AssemblyErrors {
+    pub diagnostics: Vec<Diagnostic>,
+    /// Number of errors left out because of the `--max-errors` cap.
+    pub omitted: usize,
+}
+
+impl AssemblyErrors {
+    /// Keeps the diagnostics up to and including the `max_errors`th error.
+    pub fn new(mut diagnostics: Vec<Diagnostic>, max_errors: Option<usize>) -> AssemblyErrors {
+        let total = diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count();
+
+        let mut omitted = 0;
+        if let Some(max) = max_errors.filter(|&max| total > max) {
+            let mut errors = 0;
+            let keep = diagnostics
+                .iter()
+                .position(|diagnostic| {
+                    errors += diagnostic.is_error() as usize;
+                    errors > max
+                })
+                .unwrap();
+            diagnostics.truncate(keep);
+            omitted = total - max;
+        }
+
+        AssemblyErrors { diagnostics, omitted }
+    }
+}
+
+impl fmt::Display for AssemblyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let shown = self.diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count();
+        let total = shown + self.omitted;
+        if total == 1 {
+            write!(f, "aborting due to previous error")
+        } else {
+            write!(f, "aborting due to {} previous errors", total)
+        }
+    }
+}
+
+impl Error for AssemblyErrors {}
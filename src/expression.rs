@@ -0,0 +1,223 @@
+use crate::code::MAX_CONSTANT;
+use crate::error::{AssemblyError, Span};
+use crate::lexer::{Token, TokenKind};
+use crate::symbol_table::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Shl,
+    Shr,
+}
+
+/// Constant expression in an A-instruction, like `SCREEN+32*ROW`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(i64),
+    /// Label, predefined symbol or variable allocated on an earlier line.
+    Symbol { name: String, span: Span },
+    Negate(Box<Expr>),
+    /// The span points at the operator.
+    Binary { op: BinaryOp, left: Box<Expr>, right: Box<Expr>, span: Span },
+}
+
+impl Expr {
+    /// Parses the operand of an A-instruction. Operators bind like in C,
+    /// from loosest to tightest: `|`, `&`, `<< >>`, `+ -`, `* /`, unary `-`.
+    /// Returns `None` if the tokens aren't a single valid expression.
+    pub fn parse(tokens: &[Token]) -> Option<Expr> {
+        let mut parser = ExprParser { tokens, position: 0 };
+        let expr = parser.binary(0)?;
+        if parser.position == tokens.len() {
+            Some(expr)
+        } else {
+            None
+        }
+    }
+
+    /// Evaluates the expression to the 15-bit value of an A-instruction.
+    /// `text` and `span` describe the whole operand for range errors.
+    pub fn evaluate(&self, symbols: &SymbolTable, text: &str, span: &Span) -> Result<u16, AssemblyError> {
+        match self.value(symbols)? {
+            Some(value) if (0..=MAX_CONSTANT as i64).contains(&value) => Ok(value as u16),
+            _ => Err(AssemblyError::ConstantOutOfRange { value: text.to_string(), span: span.clone() }),
+        }
+    }
+
+    /// Value of the expression, `None` when it overflows.
+    fn value(&self, symbols: &SymbolTable) -> Result<Option<i64>, AssemblyError> {
+        let value = match self {
+            Expr::Number(value) => Some(*value),
+            Expr::Symbol { name, span } => match symbols.get_address(name) {
+                Some(&address) => Some(address as i64),
+                None => return Err(AssemblyError::UndefinedSymbol { symbol: name.clone(), span: span.clone() }),
+            },
+            Expr::Negate(expr) => expr.value(symbols)?.and_then(i64::checked_neg),
+            Expr::Binary { op, left, right, span } => {
+                let (Some(x), Some(y)) = (left.value(symbols)?, right.value(symbols)?) else { return Ok(None) };
+                match op {
+                    BinaryOp::Add => x.checked_add(y),
+                    BinaryOp::Sub => x.checked_sub(y),
+                    BinaryOp::Mul => x.checked_mul(y),
+                    BinaryOp::Div if y == 0 => return Err(AssemblyError::DivisionByZero { span: span.clone() }),
+                    BinaryOp::Div => x.checked_div(y),
+                    BinaryOp::And => Some(x & y),
+                    BinaryOp::Or => Some(x | y),
+                    BinaryOp::Shl => u32::try_from(y).ok().and_then(|y| x.checked_shl(y)),
+                    BinaryOp::Shr => u32::try_from(y).ok().and_then(|y| x.checked_shr(y)),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+/// Parses a number literal: decimal, `0x` hexadecimal or `0b` binary.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x" | "0X") => (&text[2..], 16),
+        Some("0b" | "0B") => (&text[2..], 2),
+        _ => (text, 10),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Operators by precedence level, loosest first.
+const LEVELS: [&[(TokenKind, BinaryOp)]; 5] = [
+    &[(TokenKind::Operator('|'), BinaryOp::Or)],
+    &[(TokenKind::Operator('&'), BinaryOp::And)],
+    &[(TokenKind::ShiftLeft, BinaryOp::Shl), (TokenKind::ShiftRight, BinaryOp::Shr)],
+    &[(TokenKind::Operator('+'), BinaryOp::Add), (TokenKind::Operator('-'), BinaryOp::Sub)],
+    &[(TokenKind::Operator('*'), BinaryOp::Mul), (TokenKind::Operator('/'), BinaryOp::Div)],
+];
+
+struct ExprParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl ExprParser<'_> {
+    fn next_if(&mut self, kind: &TokenKind) -> Option<&Token> {
+        let token = self.tokens.get(self.position).filter(|token| token.kind == *kind)?;
+        self.position += 1;
+        Some(token)
+    }
+
+    /// Left-associative chain of the operators at `level` and tighter.
+    fn binary(&mut self, level: usize) -> Option<Expr> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        'chain: loop {
+            for (kind, op) in LEVELS[level] {
+                if let Some(token) = self.next_if(kind) {
+                    let span = token.span.clone();
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary { op: *op, left: Box::new(left), right: Box::new(right), span };
+                    continue 'chain;
+                }
+            }
+            return Some(left);
+        }
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        if self.next_if(&TokenKind::Operator('-')).is_some() {
+            return Some(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.next_if(&TokenKind::LParen).is_some() {
+            let expr = self.binary(0)?;
+            self.next_if(&TokenKind::RParen)?;
+            return Some(expr);
+        }
+
+        let token = self.tokens.get(self.position)?;
+        let expr = match &token.kind {
+            TokenKind::Integer(digits) => Expr::Number(parse_number(digits)?),
+            TokenKind::Character(c) => Expr::Number(*c as i64),
+            TokenKind::Identifier(name) => Expr::Symbol { name: name.clone(), span: token.span.clone() },
+            _ => return None,
+        };
+        self.position += 1;
+        Some(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use std::rc::Rc;
+
+    fn evaluate(source: &str, symbols: &SymbolTable) -> Result<u16, AssemblyError> {
+        let mut tokens = tokenize(source, "test.asm");
+        tokens.pop();
+        let span = Span::new(Rc::from("test.asm"), 1, 1);
+        Expr::parse(&tokens).expect("valid expression").evaluate(symbols, source, &span)
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number("21"), Some(21));
+        assert_eq!(parse_number("0x4000"), Some(0x4000));
+        assert_eq!(parse_number("0XfF"), Some(255));
+        assert_eq!(parse_number("0b1010"), Some(10));
+        for text in ["0x", "0b102", "1x", "0o7", "99999999999999999999"] {
+            assert_eq!(parse_number(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let symbols = SymbolTable::new();
+
+        assert_eq!(evaluate("SCREEN+32", &symbols), Ok(16416));
+        assert_eq!(evaluate("0x10 | 0b0011 & 6", &symbols), Ok(18));
+        assert_eq!(evaluate("1 + 2 * 3 << 1", &symbols), Ok(14));
+        assert_eq!(evaluate("(1 + 2) * 3", &symbols), Ok(9));
+        assert_eq!(evaluate("10 - 3 - 2", &symbols), Ok(5));
+        assert_eq!(evaluate("KBD >> 8 / 2", &symbols), Ok(1536));
+        assert_eq!(evaluate("'A' + -(-1)", &symbols), Ok(66));
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let symbols = SymbolTable::new();
+        let out_of_range = |value: &str| {
+            Err(AssemblyError::ConstantOutOfRange {
+                value: value.to_string(),
+                span: Span::new(Rc::from("test.asm"), 1, 1),
+            })
+        };
+
+        assert_eq!(evaluate("KBD+KBD", &symbols), out_of_range("KBD+KBD"));
+        assert_eq!(evaluate("1-2", &symbols), out_of_range("1-2"));
+        assert_eq!(evaluate("1<<64", &symbols), out_of_range("1<<64"));
+        assert_eq!(evaluate("0x7fffffffffffffff*2", &symbols), out_of_range("0x7fffffffffffffff*2"));
+        assert_eq!(
+            evaluate("ARR+1", &symbols),
+            Err(AssemblyError::UndefinedSymbol { symbol: "ARR".to_string(), span: Span::new(Rc::from("test.asm"), 1, 1) })
+        );
+        assert_eq!(
+            evaluate("4 / (R1 - 1)", &symbols),
+            Err(AssemblyError::DivisionByZero { span: Span::new(Rc::from("test.asm"), 1, 3) })
+        );
+
+        for source in ["1+", "(1", "1)", "1 2", "*1", "0x"] {
+            let mut tokens = tokenize(source, "test.asm");
+            tokens.pop();
+            assert_eq!(Expr::parse(&tokens), None, "{}", source);
+        }
+    }
+}
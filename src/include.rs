@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::{AssemblyError, Span};
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::parser::{is_directive, source_text, split_lines};
+use crate::{Options, Source};
+
+/// Tokenizes the source and replaces every `.include "file"` line by the
+/// lines of that file. The file is looked up next to the including file
+/// first, then in each of the include paths. Returns the lines and the
+/// text of every included file. Unless `options.includes` is set, every
+/// `.include` is an error and no file is touched.
+pub fn load(source: &str, file: &str, options: &Options) -> (Vec<Vec<Token>>, Vec<Source>, Vec<AssemblyError>) {
+    let mut loader = Loader { options, included: Vec::new(), errors: Vec::new(), stack: Vec::new() };
+    if options.includes {
+        if let Ok(path) = fs::canonicalize(file) {
+            loader.stack.push(path);
+        }
+    }
+
+    let mut lines = Vec::new();
+    loader.load_file(source, file, None, &mut lines);
+    (lines, loader.included, loader.errors)
+}
+
+struct Loader<'a> {
+    options: &'a Options,
+    included: Vec<Source>,
+    errors: Vec<AssemblyError>,
+    /// Files currently being included, to detect cycles.
+    stack: Vec<PathBuf>,
+}
+
+impl Loader<'_> {
+    fn load_file(&mut self, text: &str, file: &str, included_from: Option<Rc<Span>>, out: &mut Vec<Vec<Token>>) {
+        let mut tokens = tokenize(text, file);
+        if let Some(include) = included_from {
+            for token in &mut tokens {
+                token.span.included_from = Some(include.clone());
+            }
+        }
+
+        for line in split_lines(tokens) {
+            if !is_directive(&line, ".include") {
+                out.push(line);
+                continue;
+            }
+
+            match &line[..] {
+                [_, Token { kind: TokenKind::Str(path), .. }, _] => self.include(path, &line[0].span, out),
+                _ => {
+                    let directive = source_text(&line[..line.len() - 1]);
+                    self.errors.push(AssemblyError::MalformedInclude { directive, span: line[0].span.clone() });
+                }
+            }
+        }
+    }
+
+    fn include(&mut self, path: &str, span: &Span, out: &mut Vec<Vec<Token>>) {
+        let failed = |reason: String| AssemblyError::IncludeFailed { path: path.to_string(), reason, span: span.clone() };
+        if !self.options.includes {
+            self.errors.push(failed("includes are not enabled".to_string()));
+            return;
+        }
+
+        let dir = Path::new(&*span.file).parent().unwrap_or(Path::new(""));
+        let found = std::iter::once(dir.join(path))
+            .chain(self.options.include_paths.iter().map(|include_path| include_path.join(path)))
+            .find(|candidate| candidate.is_file());
+
+        let Some(found) = found else {
+            self.errors.push(failed("file not found".to_string()));
+            return;
+        };
+
+        let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+        if self.stack.contains(&canonical) {
+            self.errors.push(AssemblyError::IncludeCycle { path: path.to_string(), span: span.clone() });
+            return;
+        }
+
+        match fs::read_to_string(&found) {
+            Ok(text) => {
+                let file = found.to_string_lossy();
+                self.included.push(Source { file: Rc::from(&*file), text: text.clone() });
+                self.stack.push(canonical);
+                self.load_file(&text, &file, Some(Rc::new(span.clone())), out);
+                self.stack.pop();
+            }
+            Err(err) => self.errors.push(failed(err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn lines(lines: &[Vec<Token>]) -> Vec<String> {
+        lines.iter().map(|line| source_text(&line[..line.len() - 1])).collect()
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = TempDir::new("test_include");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("lib/math.asm"), "@R13\n.include \"inc.asm\"\n").unwrap();
+        fs::write(dir.join("lib/inc.asm"), "M=M+1\n").unwrap();
+        fs::write(dir.join("shared/screen.asm"), "@SCREEN\n").unwrap();
+
+        let main = dir.join("main.asm");
+        let source = ".include \"lib/math.asm\"\n.include \"screen.asm\"\nD=0\n";
+        let options = Options { includes: true, include_paths: vec![dir.join("shared")], ..Options::default() };
+        let (loaded, included, errors) = load(source, main.to_str().unwrap(), &options);
+
+        assert_eq!(errors, vec![]);
+        assert_eq!(lines(&loaded), vec!["@R13", "M=M+1", "@SCREEN", "D=0"]);
+        assert_eq!(included.len(), 3);
+        assert!(included[1].file.ends_with("inc.asm"));
+        assert_eq!(included[1].text, "M=M+1\n");
+
+        // M=M+1 comes from inc.asm, included by math.asm, included by main.asm
+        let span = &loaded[1][0].span;
+        assert!(span.file.ends_with("inc.asm"));
+        let include = span.included_from.as_ref().unwrap();
+        assert_eq!((include.file.ends_with("math.asm"), include.line), (true, 2));
+        assert_eq!((span.origin().file.ends_with("main.asm"), span.origin().line), (true, 1));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = TempDir::new("test_include_errors");
+        fs::write(dir.join("a.asm"), ".include \"b.asm\"\n").unwrap();
+        fs::write(dir.join("b.asm"), "D=0\n.include \"a.asm\"\n").unwrap();
+
+        let main = dir.join("a.asm");
+        let source = ".include \"b.asm\"\n.include \"missing.asm\"\n.include b.asm\n";
+        let options = Options { includes: true, ..Options::default() };
+        let (loaded, _, errors) = load(source, main.to_str().unwrap(), &options);
+
+        assert_eq!(lines(&loaded), vec!["D=0"]);
+        let errors: Vec<_> = errors.iter().map(|err| (err.to_string(), err.span().line)).collect();
+        assert_eq!(errors, vec![
+            ("`a.asm` is already being included".to_string(), 2),
+            ("can't include `missing.asm`: file not found".to_string(), 2),
+            ("malformed include `.include b.asm`, expected `.include \"file\"`".to_string(), 3),
+        ]);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    At,
+    Identifier(String),
+    /// Number starting with a digit, like `21`, `0x4000` or `0b1010`.
+    /// Checking the digits and the range is left to the parser and encoder.
+    Integer(String),
+    /// Character literal like `'A'`.
+    Character(char),
+    /// Text in double quotes, like the file of an `.include`.
+    Str(String),
+    /// One of `+ - ! & | * /`.
+    Operator(char),
+    ShiftLeft,
+    ShiftRight,
+    Equals,
+    Semicolon,
+    /// Separates macro parameters and arguments.
+    Comma,
+    LParen,
+    RParen,
+    Newline,
+    /// Any character that can't start a token, reported by the parser.
+    Unknown(char),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::At => write!(f, "@"),
+            TokenKind::Identifier(name) => write!(f, "{}", name),
+            TokenKind::Integer(digits) => write!(f, "{}", digits),
+            TokenKind::Character(c) => write!(f, "'{}'", c),
+            TokenKind::Str(text) => write!(f, "\"{}\"", text),
+            TokenKind::Operator(op) => write!(f, "{}", op),
+            TokenKind::ShiftLeft => write!(f, "<<"),
+            TokenKind::ShiftRight => write!(f, ">>"),
+            TokenKind::Equals => write!(f, "="),
+            TokenKind::Semicolon => write!(f, ";"),
+            TokenKind::Comma => write!(f, ","),
+            TokenKind::LParen => write!(f, "("),
+            TokenKind::RParen => write!(f, ")"),
+            TokenKind::Newline => Ok(()),
+            TokenKind::Unknown(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Characters allowed in symbols besides letters and digits.
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+/// Splits the source into tokens, skipping whitespace and `//` comments.
+/// Every line, including the last one, is terminated by a `Newline` token.
+pub fn tokenize(source: &str, file: &str) -> Vec<Token> {
+    let file: Rc<str> = Rc::from(file);
+    let mut tokens = Vec::new();
+
+    for (number, line) in source.lines().enumerate() {
+        let mut chars = line.chars().enumerate().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            let span = Span::new(file.clone(), number + 1, index + 1);
+            let kind = match c {
+                _ if c.is_whitespace() => continue,
+                '/' if matches!(chars.peek(), Some((_, '/'))) => break,
+                '@' => TokenKind::At,
+                '=' => TokenKind::Equals,
+                ';' => TokenKind::Semicolon,
+                ',' => TokenKind::Comma,
+                '(' => TokenKind::LParen,
+                ')' => TokenKind::RParen,
+                '+' | '-' | '!' | '&' | '|' | '*' | '/' => TokenKind::Operator(c),
+                '<' if chars.next_if(|&(_, c)| c == '<').is_some() => TokenKind::ShiftLeft,
+                '>' if chars.next_if(|&(_, c)| c == '>').is_some() => TokenKind::ShiftRight,
+                '\'' => match (chars.next(), chars.next_if(|&(_, c)| c == '\'')) {
+                    (Some((_, c)), Some(_)) => TokenKind::Character(c),
+                    _ => TokenKind::Unknown('\''),
+                },
+                '"' => {
+                    let mut text = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break TokenKind::Str(text),
+                            Some((_, c)) => text.push(c),
+                            None => break TokenKind::Unknown('"'),
+                        }
+                    }
+                }
+                _ if c.is_ascii_digit() => {
+                    let mut digits = c.to_string();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+                        digits.push(c);
+                    }
+                    TokenKind::Integer(digits)
+                }
+                _ if is_symbol_char(c) => {
+                    let mut name = c.to_string();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| is_symbol_char(*c)) {
+                        name.push(c);
+                    }
+                    TokenKind::Identifier(name)
+                }
+                _ => TokenKind::Unknown(c),
+            };
+            tokens.push(Token { kind, span });
+        }
+
+        let column = line.chars().count() + 1;
+        tokens.push(Token { kind: TokenKind::Newline, span: Span::new(file.clone(), number + 1, column) });
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize(source, "test.asm").into_iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        use TokenKind::*;
+
+        assert_eq!(kinds("@21"), vec![At, Integer("21".to_string()), Newline]);
+        assert_eq!(
+            kinds("AM=M-1;JGT"),
+            vec![
+                Identifier("AM".to_string()),
+                Equals,
+                Identifier("M".to_string()),
+                Operator('-'),
+                Integer("1".to_string()),
+                Semicolon,
+                Identifier("JGT".to_string()),
+                Newline,
+            ]
+        );
+        assert_eq!(
+            kinds("(sys.init$ret:1)"),
+            vec![LParen, Identifier("sys.init$ret:1".to_string()), RParen, Newline]
+        );
+        assert_eq!(kinds("D=!D&A|M"), vec![
+            Identifier("D".to_string()),
+            Equals,
+            Operator('!'),
+            Identifier("D".to_string()),
+            Operator('&'),
+            Identifier("A".to_string()),
+            Operator('|'),
+            Identifier("M".to_string()),
+            Newline,
+        ]);
+        assert_eq!(kinds("@1x"), vec![At, Integer("1x".to_string()), Newline]);
+        assert_eq!(
+            kinds("@(0x4000+'A')*2<<1>>0b1/3"),
+            vec![
+                At,
+                LParen,
+                Integer("0x4000".to_string()),
+                Operator('+'),
+                Character('A'),
+                RParen,
+                Operator('*'),
+                Integer("2".to_string()),
+                ShiftLeft,
+                Integer("1".to_string()),
+                ShiftRight,
+                Integer("0b1".to_string()),
+                Operator('/'),
+                Integer("3".to_string()),
+                Newline,
+            ]
+        );
+        assert_eq!(kinds("@'A"), vec![At, Unknown('\''), Newline]);
+        assert_eq!(
+            kinds(".include \"lib/math.asm\" \"x"),
+            vec![Identifier(".include".to_string()), Str("lib/math.asm".to_string()), Unknown('"'), Newline]
+        );
+        assert_eq!(kinds("INC i,j"), vec![
+            Identifier("INC".to_string()),
+            Identifier("i".to_string()),
+            Comma,
+            Identifier("j".to_string()),
+            Newline,
+        ]);
+        assert_eq!(kinds("D=#<"), vec![Identifier("D".to_string()), Equals, Unknown('#'), Unknown('<'), Newline]);
+    }
+
+    #[test]
+    fn test_tokenize_comments_and_spans() {
+        let tokens = tokenize("// comment\n\n  @i // in-line comment\n", "test.asm");
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Newline,
+                TokenKind::Newline,
+                TokenKind::At,
+                TokenKind::Identifier("i".to_string()),
+                TokenKind::Newline,
+            ]
+        );
+        assert_eq!((tokens[2].span.line, tokens[2].span.column), (3, 3));
+        assert_eq!((tokens[3].span.line, tokens[3].span.column), (3, 4));
+        assert_eq!(&*tokens[3].span.file, "test.asm");
+    }
+}
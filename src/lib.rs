@@ -1,608 +1,1036 @@
 use std::fs;
+use std::io;
 use std::error::Error;
-use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+mod code;
+pub mod debug_info;
+pub mod disassembler;
+pub mod emulator;
+mod error;
+pub mod expression;
+mod include;
+mod macros;
+pub mod lexer;
+pub mod listing;
+pub mod memory;
+pub mod output;
+pub mod parser;
+pub mod symbol_map;
+mod symbol_table;
+pub mod test_script;
+
+use code::{Code, MAX_CONSTANT};
+use memory::{RAM_SIZE, ROM_SIZE, SCREEN};
+
+pub use disassembler::{disassemble, DisassemblyOptions};
+pub use emulator::Emulator;
+pub use output::{parse_intel_hex, parse_srecord, Endian, OutputFormat, OutputOptions};
+pub use error::{AssemblyError, AssemblyErrors, Diagnostic, Expansion, FormatError, Span, Warning};
+pub use symbol_map::{symbol_map, SymbolMapFormat};
+pub use symbol_table::{SymbolKind, SymbolTable};
+pub use parser::{Field, Operand, Statement};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Assemble,
+    Disassemble,
+    Run,
+    Test,
+}
+
+/// Default for the `--cycles` limit of the run command.
+const DEFAULT_CYCLES: usize = 1_000_000;
 
 pub struct Config {
-    input_file: String,
+    command: Command,
+    /// Files assembled into one program, the other commands take one file.
+    input_files: Vec<String>,
+    /// Empty for commands that don't write a file.
     output_file: String,
+    /// Maximum number of errors to report, `None` reports all of them.
+    max_errors: Option<usize>,
+    options: Options,
+    disassembly: DisassemblyOptions,
+    output: OutputOptions,
+    /// Where to write the listing of the assembled program.
+    listing_file: Option<String>,
+    /// Where to write the symbol map and in which format.
+    symbol_file: Option<String>,
+    symbol_format: SymbolMapFormat,
+    /// Where to write the debug info mapping ROM addresses to the source.
+    debug_info_file: Option<String>,
+    /// Number of instructions the run command executes at most.
+    cycles: usize,
+    /// RAM addresses the run command prints when it stops.
+    ram_cells: Vec<usize>,
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
-        args.next();
-
-        let input_file = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't provide input file"),
-        };
-
-        let output_file = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't provide output file"),
+    pub fn new(args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
+        let mut args = args.skip(1).peekable();
+
+        let command = match args.next_if(|arg| ["disassemble", "run", "test"].contains(&&arg[..])).as_deref() {
+            Some("disassemble") => Command::Disassemble,
+            Some("run") => Command::Run,
+            Some(_) => Command::Test,
+            None => Command::Assemble,
         };
 
-        Ok(Config { input_file, output_file })
-    }
-}
-
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let mut assembler = HackAssembler::new(&config);
-    let mut parser = Parser::new(&config)?;
-    let mut symbols = SymbolTable::new();
-
-    // First pass
-    loop {
-        match parser.instruction_type() {
-            Some(Instruction::L) => {
-                // add to the symbol table
-                symbols.add_entry(parser.symbol().unwrap(), parser.current_instruction as i32);
-                // remove that line, so further symbols match the lines
-                if parser.has_more_lines() {
-                    // do not advance here!
-                    parser.lines.remove(parser.current_instruction);
-                    continue;
-                } else {
-                    parser.lines.remove(parser.current_instruction);
-                    break;
+        let mut positional = Vec::new();
+        let mut output = None;
+        let mut max_errors = None;
+        let mut options = Options { includes: true, ..Options::default() };
+        let mut disassembly = DisassemblyOptions::default();
+        let mut output_options = OutputOptions::default();
+        let mut listing_file = None;
+        let mut symbol_file = None;
+        let mut symbol_format = SymbolMapFormat::default();
+        let mut debug_info_file = None;
+        let mut cycles = DEFAULT_CYCLES;
+        let mut ram_cells = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "--max-errors" => {
+                    max_errors = match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(0)) => None,
+                        Some(Ok(n)) => Some(n),
+                        _ => return Err("--max-errors expects a number"),
+                    };
+                }
+                "--ram-budget" => {
+                    options.ram_budget = match args.next().map(|n| n.parse::<i32>()) {
+                        Some(Ok(n)) if n >= 0 => Some(n),
+                        _ => return Err("--ram-budget expects a number"),
+                    };
+                }
+                "--listing" => {
+                    listing_file = match args.next() {
+                        Some(file) => Some(file),
+                        None => return Err("--listing expects a file name"),
+                    };
+                }
+                "--sym" => {
+                    symbol_file = match args.next() {
+                        Some(file) => Some(file),
+                        None => return Err("--sym expects a file name"),
+                    };
+                }
+                "--sym-format" => {
+                    symbol_format = match args.next().as_deref() {
+                        Some("text") => SymbolMapFormat::Text,
+                        Some("json") => SymbolMapFormat::Json,
+                        _ => return Err("--sym-format expects text or json"),
+                    };
+                }
+                "--debug-info" => {
+                    debug_info_file = match args.next() {
+                        Some(file) => Some(file),
+                        None => return Err("--debug-info expects a file name"),
+                    };
+                }
+                "-o" | "--output" => match args.next() {
+                    Some(file) => output = Some(file),
+                    None => return Err("-o expects a file name"),
+                },
+                "--format" => {
+                    output_options.format = match args.next().as_deref().and_then(OutputFormat::from_name) {
+                        Some(format) => format,
+                        None => return Err("--format expects hack, bin, ihex, srec, memh, memb, coe, mif, vhdl, logisim, digital, c, rust or python"),
+                    };
+                }
+                "--endian" => {
+                    output_options.endian = match args.next().as_deref() {
+                        Some("big") => Endian::Big,
+                        Some("little") => Endian::Little,
+                        _ => return Err("--endian expects big or little"),
+                    };
+                }
+                "--rom-size" => {
+                    output_options.rom_size = match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(n)) if n <= ROM_SIZE => Some(n),
+                        _ => return Err("--rom-size expects a number of words up to 32768"),
+                    };
                 }
+                "--record-length" => {
+                    output_options.record_length = match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(n)) if (1..=250).contains(&n) => n,
+                        _ => return Err("--record-length expects a number of bytes from 1 to 250"),
+                    };
+                }
+                "--fill" => {
+                    output_options.fill = match args.next().as_deref().and_then(expression::parse_number) {
+                        Some(word) if word <= u16::MAX as i64 => word as u16,
+                        _ => return Err("--fill expects a 16-bit word like 0 or 0xffff"),
+                    };
+                }
+                "--comments" => output_options.comments = true,
+                "--lint" => options.lint = true,
+                "-I" | "--include-path" => match args.next() {
+                    Some(dir) => options.include_paths.push(PathBuf::from(dir)),
+                    None => return Err("--include-path expects a directory"),
+                },
+                "--labels" => disassembly.labels = true,
+                "--symbols" => disassembly.symbols = true,
+                "--cycles" => {
+                    cycles = match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(n)) => n,
+                        _ => return Err("--cycles expects a number"),
+                    };
+                }
+                "--ram" => {
+                    ram_cells = match args.next().map(|cells| parse_ram_cells(&cells)) {
+                        Some(Some(cells)) => cells,
+                        _ => return Err("--ram expects addresses like 0,1,16-20"),
+                    };
+                }
+                _ if arg.starts_with("--") => return Err("Unknown option"),
+                _ => positional.push(arg),
             }
-            _ => (),
         }
 
-        if !parser.has_more_lines() {
-            break;
+        // without -o the output file follows the input file
+        let output_file = match (command, output) {
+            (Command::Run | Command::Test, Some(_)) => return Err("-o can't be used with run and test"),
+            (Command::Run | Command::Test, None) => String::new(),
+            (_, Some(file)) => file,
+            // `a.asm b.asm` would overwrite b.asm, it's more likely two inputs
+            (Command::Assemble, None) if positional.len() == 2 && positional[1].ends_with(".asm") => {
+                return Err("Use -o to name the output of several input files")
+            }
+            (_, None) if positional.len() == 2 => positional.pop().unwrap(),
+            (_, None) if positional.len() > 2 => return Err("Use -o to name the output of several input files"),
+            (_, None) if positional.len() == 1 => return Err("Didn't provide output file"),
+            (_, None) => String::new(),
+        };
+
+        let input_files = positional;
+        if input_files.is_empty() {
+            return Err("Didn't provide input file");
+        }
+        if input_files.len() > 1 && command != Command::Assemble {
+            return Err("Too many arguments");
         }
 
-        parser.advance();
+        Ok(Config {
+            command,
+            input_files,
+            output_file,
+            max_errors,
+            options,
+            disassembly,
+            output: output_options,
+            listing_file,
+            symbol_file,
+            symbol_format,
+            debug_info_file,
+            cycles,
+            ram_cells,
+        })
     }
+}
 
-    // reset parser
-    parser.current_instruction = 0;
-
-    // Second pass
-    loop {
-        match parser.instruction_type() {
-            Some(Instruction::A) => {
-                match parser.symbol().unwrap().parse::<i32>() {
-                    Ok(num) => {
-                        let binary = format!("{:016b}", num);
-                        assembler.add_bytecode(&binary);
-                    },
-                    _ => {
-                        // ether label or variable
-                        if symbols.contains(&parser.symbol().unwrap()) {
-                            let address: &i32 = symbols.get_address(&parser.symbol().unwrap()).unwrap();
-                            let binary = format!("{:016b}", address);
-                            assembler.add_bytecode(&binary);
-                        } else {
-                            // this is a variable
-                            let binary = format!("{:016b}", parser.current_variable_address);
-                            assembler.add_bytecode(&binary);
-
-                            symbols.add_entry(parser.symbol().unwrap(), parser.current_variable_address);
-                            parser.current_variable_address += 1;
-                        }
-                    },
-                }
-            },
-            Some(Instruction::C) => {
-                let mut binary = String::from("111");
-                binary += &Code::comp(parser.comp());
-                binary += &Code::dest(parser.dest());
-                binary += &Code::jump(parser.jump());
-                assembler.add_bytecode(&binary);
-            }
-            _ => (),
+/// Parses a comma separated list of addresses and inclusive ranges.
+fn parse_ram_cells(cells: &str) -> Option<Vec<usize>> {
+    let mut addresses = Vec::new();
+    for cell in cells.split(',') {
+        let (first, last) = cell.split_once('-').unwrap_or((cell, cell));
+        let (first, last) = (first.trim().parse::<usize>().ok()?, last.trim().parse::<usize>().ok()?);
+        if first > last || last >= RAM_SIZE {
+            return None;
         }
+        addresses.extend(first..=last);
+    }
+    Some(addresses)
+}
 
-        if !parser.has_more_lines() {
-            break;
+/// Runs the command given on the command line, returning the warnings
+/// issued along the way.
+pub fn run(config: Config) -> Result<Vec<Warning>, Box<dyn Error>> {
+    match config.command {
+        Command::Assemble => run_assembler(&config),
+        Command::Disassemble => {
+            run_disassembler(&config)?;
+            Ok(Vec::new())
+        }
+        Command::Run => run_emulator(&config),
+        Command::Test => {
+            run_test_script(&config)?;
+            Ok(Vec::new())
         }
+    }
+}
 
-        parser.advance();
+fn run_assembler(config: &Config) -> Result<Vec<Warning>, Box<dyn Error>> {
+    let sources = config
+        .input_files
+        .iter()
+        .map(|file| Ok(Source { file: Rc::from(&file[..]), text: fs::read_to_string(file)? }))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    let program = assemble_files(&sources, &config.options)
+        .map_err(|diagnostics| AssemblyErrors::new(diagnostics, config.max_errors))?;
+
+    if let Some(file) = &config.listing_file {
+        fs::write(file, listing::listing(&program))?;
+    }
+    if let Some(file) = &config.symbol_file {
+        fs::write(file, symbol_map(&program.symbols, config.symbol_format))?;
+    }
+    if let Some(file) = &config.debug_info_file {
+        fs::write(file, debug_info::debug_info(&program))?;
     }
 
+    let comments = if config.output.comments { program.instructions.clone() } else { Vec::new() };
+    let mut words = program.words;
+    config.output.pad(&mut words)?;
+    let assembler = HackAssembler::new(config, words, comments);
     assembler.write_to_file()?;
 
-    Ok(())
+    Ok(program.warnings)
 }
 
-struct Parser {
-    lines: Vec<String>,
-    current_instruction: usize,
-    current_variable_address: i32,
-}
+fn run_disassembler(config: &Config) -> Result<(), Box<dyn Error>> {
+    let words = parse_machine_code(&fs::read_to_string(&config.input_files[0])?, config.output.endian)?;
+    let asm = disassemble(&words, &config.disassembly)?;
+    fs::write(&config.output_file, asm)?;
 
-#[derive(Debug)]
-enum Instruction {
-    A,
-    C,
-    L,
+    Ok(())
 }
 
-impl Parser {
-    fn new(config: &Config) -> Result<Parser, Box<dyn Error>> {
-        let source = fs::read_to_string(&config.input_file)?;
-
-        Ok(Parser::create(source))
+/// Runs a machine code file, or a source file after assembling it, and prints
+/// the selected RAM cells.
+fn run_emulator(config: &Config) -> Result<Vec<Warning>, Box<dyn Error>> {
+    let input_file = &config.input_files[0];
+    let contents = fs::read_to_string(input_file)?;
+    let (words, warnings) = if is_machine_code(Path::new(input_file)) {
+        (parse_machine_code(&contents, config.output.endian)?, Vec::new())
+    } else {
+        let program = assemble_source(&contents, input_file, &config.options)
+            .map_err(|diagnostics| AssemblyErrors::new(diagnostics, config.max_errors))?;
+        (program.words, program.warnings)
+    };
+
+    let mut emulator = Emulator::new(&words);
+    let cycles = emulator.run(config.cycles);
+    if emulator.is_halted() {
+        println!("Halted after {} cycles", cycles);
+    } else {
+        println!("Stopped after {} cycles", cycles);
+    }
+    for &address in &config.ram_cells {
+        println!("RAM[{}] = {}", address, emulator.ram[address] as i16);
     }
 
-    fn create(contents: String) -> Parser {
-        let mut parser = Parser {
-            lines: Vec::new(),
-            current_instruction: 0,
-            current_variable_address: 16
-        };
+    Ok(warnings)
+}
 
-        parser.lines = contents
-            .lines()
-            .map(|line| {
-                match line.find("//") {
-                    Some(index) => &line[..index],
-                    None => line
-                }
-            })
-            .map(|line| line.trim())
-            .filter(|line| !line.is_empty())
-            .map(|line| line.to_string())
-            .collect();
+/// Runs a `.tst` script and prints the lines that differ from its `.cmp` file.
+fn run_test_script(config: &Config) -> Result<(), Box<dyn Error>> {
+    let script = test_script::parse_script(&fs::read_to_string(&config.input_files[0])?)?;
+    let dir = Path::new(&config.input_files[0]).parent().unwrap_or(Path::new(""));
+    let report = test_script::run_script(&script, dir)?;
 
-        parser
+    for mismatch in &report.mismatches {
+        println!("Comparison failure at line {}", mismatch.line);
+        println!("  expected: {}", mismatch.expected.as_deref().unwrap_or("<end of file>"));
+        println!("  actual:   {}", mismatch.actual);
     }
 
-    fn has_more_lines(&self) -> bool {
-        self.lines.len() > self.current_instruction + 1
+    match (&report.compare_file, report.mismatches.len()) {
+        (Some(file), 0) => println!("Comparison with {} ended successfully", file),
+        (Some(file), n) => return Err(format!("{} output lines differ from {}", n, file).into()),
+        (None, _) => print!("{}", report.output),
     }
 
-    fn advance(&mut self) {
-        self.current_instruction += 1;
-    }
+    Ok(())
+}
 
-    // TODO: what if wrong line? should break, not just say its C_INSTRUCTION
-    fn instruction_type(&self) -> Option<Instruction> {
-        if self.current_instruction < self.lines.len() {
-            let line = &self.lines[self.current_instruction];
-            if line.starts_with('@') {
-                Some(Instruction::A)
-            } else if line.starts_with('(') && line.ends_with(')') {
-                Some(Instruction::L)
-            } else {
-                Some(Instruction::C)
-            }
-        } else {
-            None
-        }
-    }
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Number of RAM words variables may use before a warning is issued.
+    pub ram_budget: Option<i32>,
+    /// Warn about comp and dest fields with non-canonical spellings.
+    pub lint: bool,
+    /// Read `.include` files from the filesystem. Off by default, so that
+    /// assembling a string never touches any file.
+    pub includes: bool,
+    /// Directories searched for `.include` files not found next to the
+    /// including file.
+    pub include_paths: Vec<PathBuf>,
+}
 
-    fn symbol(&self) -> Option<String> {
-        let line = &self.lines[self.current_instruction];
-        match self.instruction_type() {
-            Some(Instruction::A) => match &line[1..] {
-                "R0" => Some("0".to_string()),
-                "R1" => Some("1".to_string()),
-                "R2" => Some("2".to_string()),
-                "R3" => Some("3".to_string()),
-                "R4" => Some("4".to_string()),
-                "R5" => Some("5".to_string()),
-                "R6" => Some("6".to_string()),
-                "R7" => Some("7".to_string()),
-                "R8" => Some("8".to_string()),
-                "R9" => Some("9".to_string()),
-                "R10" => Some("10".to_string()),
-                "R11" => Some("11".to_string()),
-                "R12" => Some("12".to_string()),
-                "R13" => Some("13".to_string()),
-                "R14" => Some("14".to_string()),
-                "R15" => Some("15".to_string()),
-                "SCREEN" => Some("16384".to_string()),
-                "KBD" => Some("24576".to_string()),
-                "SP" => Some("0".to_string()),
-                "LCL" => Some("1".to_string()),
-                "ARG" => Some("2".to_string()),
-                "THIS" => Some("3".to_string()),
-                "THAT" => Some("4".to_string()),
-                _ => Some(line[1..].to_string()),
-            },
-            Some(Instruction::L) => {
-                let matches: &[_] = &['(', ')'];
-                Some(line.trim_matches(matches).to_string())
-            }
-            _ => None,
-        }
+/// Text of a file the program was assembled from.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub file: Rc<str>,
+    pub text: String,
+}
+
+/// Result of assembling a source file.
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// Machine code, one word per ROM address.
+    pub words: Vec<u16>,
+    /// Labels, variables and predefined symbols with their addresses.
+    pub symbols: SymbolTable,
+    /// Location of the instruction each word was assembled from.
+    pub source_map: Vec<Span>,
+    /// Text of the instruction each word was assembled from, after macro
+    /// expansion.
+    pub instructions: Vec<String>,
+    pub warnings: Vec<Warning>,
+    /// The files given to the assembler.
+    pub sources: Vec<Source>,
+    /// The files included by them.
+    pub included: Vec<Source>,
+}
+
+/// Assembles Hack assembly source into machine code with default options.
+/// On failure all errors and warnings are returned, sorted by location.
+pub fn assemble(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    assemble_source(source, "<source>", &Options::default())
+}
+
+/// Like [`assemble`], with `file` used as the file name in spans.
+pub fn assemble_source(source: &str, file: &str, options: &Options) -> Result<Program, Vec<Diagnostic>> {
+    assemble_files(&[Source { file: Rc::from(file), text: source.to_string() }], options)
+}
+
+/// Assembles the files as one program, in the given order. Labels and
+/// macros are shared by all files.
+pub fn assemble_files(files: &[Source], options: &Options) -> Result<Program, Vec<Diagnostic>> {
+    let mut lines = Vec::new();
+    let mut included = Vec::new();
+    let mut errors = Vec::new();
+    for source in files {
+        let (file_lines, file_included, file_errors) = include::load(&source.text, &source.file, options);
+        lines.extend(file_lines);
+        included.extend(file_included);
+        errors.extend(file_errors);
     }
 
-    fn dest(&self) -> Option<String> {
-        let line = &self.lines[self.current_instruction];
-        match self.instruction_type() {
-            Some(Instruction::C) => {
-                match line.find('=') {
-                    Some(pos) => Some(line[..pos].to_string()),
-                    None => None,
+    let mut symbols = SymbolTable::new();
+    let (statements, parse_errors) = parser::parse_lines(lines);
+    errors.extend(parse_errors);
+    let mut warnings = Vec::new();
+    let mut words = Vec::new();
+    let mut source_map = Vec::new();
+    let mut instructions = Vec::new();
+
+    // First pass
+    let is_code = |statement: &&Statement| matches!(statement, Statement::AInstr { .. } | Statement::CInstr { .. });
+    let size = statements.iter().filter(is_code).count();
+    let mut address = 0;
+    for statement in &statements {
+        match statement {
+            Statement::Label { name, span } => {
+                if symbols.contains(name) {
+                    errors.push(AssemblyError::DuplicateLabel { label: name.clone(), span: span.clone() });
+                } else {
+                    symbols.add_entry(name.clone(), address as i32, SymbolKind::Label, span.clone());
                 }
             }
-            _ => None,
-        }
-    }
-
-    fn comp(&self) -> Option<String> {
-        let line = &self.lines[self.current_instruction];
-        match self.instruction_type() {
-            Some(Instruction::C) => {
-                let start = match line.find('=') {
-                    Some(pos) => pos + 1,
-                    None => 0,
-                };
-                let end = line.find(';');
-                if end.is_some() {
-                    let end = end.unwrap();
-                    Some(line[start..end].to_string())
+            // constants may use the constants and labels defined above them
+            Statement::Constant { name, value, text, span } => {
+                if symbols.contains(name) {
+                    errors.push(AssemblyError::DuplicateConstant { constant: name.clone(), span: span.clone() });
                 } else {
-                    Some(line[start..].to_string())
+                    match value.evaluate(&symbols, text, span) {
+                        Ok(value) => symbols.add_entry(name.clone(), value as i32, SymbolKind::Constant, span.clone()),
+                        Err(err) => errors.push(err),
+                    }
                 }
             }
-            _ => None,
+            _ => {
+                if address == ROM_SIZE {
+                    errors.push(AssemblyError::RomOverflow { size, span: statement.span().clone() });
+                }
+                address += 1;
+            }
         }
     }
 
-    fn jump(&self) -> Option<String> {
-        let line = &self.lines[self.current_instruction];
-        match self.instruction_type() {
-            Some(Instruction::C) => {
-                let start = line.find(';');
-                if start.is_some() {
-                    let start = start.unwrap() + 1;
-                    Some(line[start..].to_string())
+    // Second pass
+    let mut next_variable = FIRST_VARIABLE;
+    for statement in &statements {
+        let word = match statement {
+            Statement::AInstr { operand: Operand::Constant(value), span } => Code::constant(value, span),
+            Statement::AInstr { operand: Operand::Expression { expr, text }, span } => expr.evaluate(&symbols, text, span),
+            Statement::AInstr { operand: Operand::Symbol(symbol), span } => {
+                // ether label or variable
+                if let Some(&address) = symbols.get_address(symbol) {
+                    if address > MAX_CONSTANT {
+                        Err(AssemblyError::ConstantOutOfRange { value: symbol.clone(), span: span.clone() })
+                    } else {
+                        Ok(address as u16)
+                    }
                 } else {
-                    None
+                    // this is a variable
+                    let address = next_variable;
+                    if options.ram_budget == Some(address - FIRST_VARIABLE) {
+                        warnings.push(Warning::RamBudgetExceeded {
+                            variable: symbol.clone(),
+                            budget: address - FIRST_VARIABLE,
+                            span: span.clone(),
+                        });
+                    }
+
+                    symbols.add_entry(symbol.clone(), address, SymbolKind::Variable, span.clone());
+                    next_variable += 1;
+
+                    if address >= SCREEN as i32 {
+                        Err(AssemblyError::RamOverflow { variable: symbol.clone(), address, span: span.clone() })
+                    } else {
+                        Ok(address as u16)
+                    }
                 }
             }
-            _ => None,
-        }
-    }
-}
-
-struct Code;
-
-impl Code {
-    fn dest(dest: Option<String>) -> String {
-        match dest {
-            None => String::from("000"),
-            Some(d) => match &d[..] {
-                "M" => String::from("001"),
-                "D" => String::from("010"),
-                "DM" => String::from("011"), // as defined in assembler slides
-                "MD" => String::from("011"), // as used in project/06/rect
-                "A" => String::from("100"),
-                "AM" => String::from("101"),
-                "AD" => String::from("110"),
-                "ADM" => String::from("111"), // as defined in assembler slides
-                "AMD" => String::from("111"), // as used in projects/05/CPU.tst:67: // AMD=D+A
-                _ => panic!("Invalid dest: {}", d),
+            Statement::CInstr { dest, comp, jump, .. } => {
+                match (Code::comp(comp), Code::dest(dest.as_ref()), Code::jump(jump.as_ref())) {
+                    (Ok(comp_bits), Ok(dest_bits), Ok(jump)) => {
+                        if options.lint {
+                            let fields = [
+                                (dest.as_ref(), Code::dest_mnemonic(dest_bits)),
+                                (Some(comp), Code::comp_mnemonic(comp_bits)),
+                            ];
+                            for (field, canonical) in fields {
+                                if let (Some(field), Some(canonical)) = (field, canonical) {
+                                    if field.text != canonical {
+                                        warnings.push(Warning::NonCanonical {
+                                            found: field.text.clone(),
+                                            canonical,
+                                            span: field.span.clone(),
+                                        });
+                                    }
+                                }
+                            }
+                        }
+                        Ok(Code::c_instruction(comp_bits, dest_bits, jump))
+                    }
+                    (comp, dest, jump) => {
+                        errors.extend([comp.err(), dest.err(), jump.err()].into_iter().flatten());
+                        continue;
+                    }
+                }
             }
-        }
-    }
+            Statement::Label { .. } | Statement::Constant { .. } => continue,
+        };
 
-    fn jump(jump: Option<String>) -> String {
-        match jump {
-            None => String::from("000"),
-            Some(cond) => match &cond[..] {
-                "JGT" => String::from("001"),
-                "JEQ" => String::from("010"),
-                "JGE" => String::from("011"),
-                "JLT" => String::from("100"),
-                "JNE" => String::from("101"),
-                "JLE" => String::from("110"),
-                "JMP" => String::from("111"),
-                _ => panic!("Invalid jump condition: {}", cond),
+        match word {
+            Ok(word) => {
+                words.push(word);
+                source_map.push(statement.span().clone());
+                instructions.push(statement.to_string());
             }
+            Err(err) => errors.push(err),
         }
     }
 
-    fn comp(comp: Option<String>) -> String {
-        match comp {
-            None => panic!("No comp provided!"),
-            Some(comp) => match &comp[..] {
-                "0" => "0101010".to_string(),
-                "1" => "0111111".to_string(),
-                "-1" => "0111010".to_string(),
-                "D" => "0001100".to_string(),
-                "A" => "0110000".to_string(),
-                "!D" => "0001101".to_string(),
-                "!A" => "0110011".to_string(),
-                "-D" => "0001111".to_string(),
-                "-A" => "0110011".to_string(),
-                "D+1" => "0011111".to_string(),
-                "A+1" => "0110111".to_string(),
-                "D-1" => "0001110".to_string(),
-                "A-1" => "0110010".to_string(),
-                "D+A" => "0000010".to_string(),
-                "D-A" => "0010011".to_string(),
-                "A-D" => "0000111".to_string(),
-                "D&A" => "0000000".to_string(),
-                "D|A" => "0010101".to_string(),
-                "M" => "1110000".to_string(),
-                "!M" => "1110001".to_string(),
-                "-M" => "1110011".to_string(),
-                "M+1" => "1110111".to_string(),
-                "M-1" => "1110010".to_string(),
-                "D+M" => "1000010".to_string(),
-                "D-M" => "1010011".to_string(),
-                "M-D" => "1000111".to_string(),
-                "D&M" => "1000000".to_string(),
-                "D|M" => "1010101".to_string(),
-                _ => panic!("Invalid comp: {}", comp),
-            }
-        }
+    if !errors.is_empty() {
+        let mut diagnostics: Vec<Diagnostic> = errors
+            .into_iter()
+            .map(Diagnostic::Error)
+            .chain(warnings.into_iter().map(Diagnostic::Warning))
+            .collect();
+        diagnostics.sort_by_key(|diagnostic| {
+            let (span, origin) = (diagnostic.span(), diagnostic.span().origin());
+            let file = files.iter().position(|source| source.file == origin.file);
+            (file, origin.line, origin.column, span.line, span.column)
+        });
+        return Err(diagnostics);
     }
-}
 
-struct SymbolTable {
-    symbols: HashMap<String, i32>,
+    Ok(Program { words, symbols, source_map, instructions, warnings, sources: files.to_vec(), included })
 }
 
-impl SymbolTable {
-    fn new() -> SymbolTable {
-        SymbolTable {
-            symbols: HashMap::new(),
-        }
-    }
-
-    fn contains(&self, symbol: &str) -> bool {
-        self.symbols.contains_key(symbol)
-    }
-
-    fn add_entry(&mut self, symbol: String, address: i32) {
-        self.symbols.insert(symbol, address);
-    }
-
-    fn get_address(&self, symbol: &str) -> Option<&i32> {
-        self.symbols.get(symbol)
-    }
-}
+/// RAM address of the first variable, right after R0..R15.
+const FIRST_VARIABLE: i32 = 16;
 
 struct HackAssembler {
     output_file: String,
-    bytecode: String,
+    words: Vec<u16>,
+    output: OutputOptions,
+    /// Instruction text of each word, for formats with comments.
+    comments: Vec<String>,
 }
 
 impl HackAssembler {
-    fn new(config: &Config) -> HackAssembler {
+    fn new(config: &Config, words: Vec<u16>, comments: Vec<String>) -> HackAssembler {
         HackAssembler {
             output_file: config.output_file.clone(),
-            bytecode: String::new(),
+            words,
+            output: config.output.clone(),
+            comments,
         }
     }
 
     fn write_to_file(&self) -> Result<(), Box<dyn Error>> {
-        fs::write(self.output_file.clone(), self.bytecode.clone())?;
+        fs::write(&self.output_file, self.render())?;
 
         Ok(())
     }
 
-    fn add_bytecode(&mut self, bytecode: &str) -> Result<(), String> {
-        if bytecode.len() != 16 {
-            return Err("Wrong size, should be 16 chars!".to_string());
+    /// Renders the words in the `.hack` format, one binary word per line.
+    fn to_text(&self) -> String {
+        let mut text = String::with_capacity(self.words.len() * 17);
+        for word in &self.words {
+            writeln!(text, "{:016b}", word).unwrap();
         }
+        text
+    }
+}
 
-        self.bytecode += bytecode;
-        self.bytecode += "\n";
+/// Reads machine code in the `.hack` format, blank lines are skipped.
+pub fn parse_hack(text: &str) -> Result<Vec<u16>, FormatError> {
+    text.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line, word)| {
+            if word.len() != 16 || !word.bytes().all(|b| b == b'0' || b == b'1') {
+                return Err(FormatError { line, message: format!("expected 16 binary digits, found `{}`", word) });
+            }
+            Ok(u16::from_str_radix(word, 2).unwrap())
+        })
+        .collect()
+}
 
-        Ok(())
+/// Reads machine code in the `.hack`, Intel HEX or S-record format, told
+/// apart by the first character of the file.
+pub fn parse_machine_code(text: &str, endian: Endian) -> Result<Vec<u16>, FormatError> {
+    match text.trim_start().chars().next() {
+        Some(':') => parse_intel_hex(text, endian),
+        Some('S') => parse_srecord(text, endian),
+        _ => parse_hack(text),
     }
 }
 
+/// Whether the file extension is one of machine code rather than source.
+pub(crate) fn is_machine_code(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    matches!(extension, Some("hack" | "hex" | "ihex" | "srec" | "s19" | "mot"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::ops::Deref;
+    use std::rc::Rc;
+
+    /// Directory for the files of one test, named after the test and the
+    /// process so that parallel and concurrent runs don't share it. It is
+    /// removed when dropped.
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(test: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("hack_assembler_{}_{}", test, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
 
-    #[test]
-    fn test_parser_create() {
-        let contents = String::from("\
-// comment
+    impl Deref for TempDir {
+        type Target = Path;
 
-@2
-@3  // in-line comment");
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
 
-        let mut parser = Parser::create(contents);
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 
-        assert_eq!(parser.lines, vec!["@2", "@3"]);
-        assert!(parser.has_more_lines());
-        parser.advance();
-        assert!(!parser.has_more_lines());
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        let args: Vec<String> = std::iter::once("hack_assembler")
+            .chain(args.iter().copied())
+            .map(String::from)
+            .collect();
+        args.into_iter()
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Config::new(args(&["in.asm", "out.hack"])).unwrap();
+        assert_eq!((&config.input_files[..], &config.output_file[..]), (&["in.asm".to_string()][..], "out.hack"));
+        assert_eq!(config.max_errors, None);
+        assert_eq!(config.command, Command::Assemble);
+
+        let config = Config::new(args(&["run", "--cycles", "100", "--ram", "0,2,16-18", "in.asm"])).unwrap();
+        assert_eq!(config.command, Command::Run);
+        assert_eq!((config.cycles, config.ram_cells), (100, vec![0, 2, 16, 17, 18]));
+        assert!(Config::new(args(&["run", "--ram", "3-1", "in.asm"])).is_err());
+        assert!(Config::new(args(&["run", "--ram", "32768", "in.asm"])).is_err());
+        assert!(Config::new(args(&["run", "in.asm", "out.hack"])).is_err());
+        assert_eq!(Config::new(args(&["test", "Mult.tst"])).unwrap().command, Command::Test);
+
+        let config = Config::new(args(&["disassemble", "--labels", "in.hack", "out.asm"])).unwrap();
+        assert_eq!(config.command, Command::Disassemble);
+        assert!(config.disassembly.labels && !config.disassembly.symbols);
+        assert!(!config.options.lint);
+        assert!(Config::new(args(&["--lint", "in.asm", "out.hack"])).unwrap().options.lint);
+        let config = Config::new(args(&["--format", "bin", "--endian", "little", "--rom-size", "32768", "--fill", "0xffff", "in.asm", "out.bin"])).unwrap();
+        assert_eq!(config.output.format, OutputFormat::Binary);
+        assert_eq!((config.output.endian, config.output.rom_size, config.output.fill), (Endian::Little, Some(32768), 0xffff));
+        assert!(Config::new(args(&["--fill", "0x10000", "in.asm", "out.bin"])).is_err());
+        assert!(Config::new(args(&["--rom-size", "32769", "in.asm", "out.bin"])).is_err());
+        assert!(Config::new(args(&["--format", "exe", "in.asm", "out.bin"])).is_err());
+        let config = Config::new(args(&["--format", "srec", "--record-length", "32", "in.asm", "out.srec"])).unwrap();
+        assert_eq!((config.output.format, config.output.record_length), (OutputFormat::SRecord, 32));
+        assert!(Config::new(args(&["--record-length", "0", "in.asm", "out.hex"])).is_err());
+        let config = Config::new(args(&["--format", "c", "--comments", "in.asm", "prog.h"])).unwrap();
+        assert_eq!((config.output.format, config.output.comments), (OutputFormat::C, true));
+        let config = Config::new(args(&["-I", "lib", "in.asm", "--include-path", "/usr/share/hack", "out.hack"])).unwrap();
+        assert!(config.options.includes);
+        assert_eq!(config.options.include_paths, vec![PathBuf::from("lib"), PathBuf::from("/usr/share/hack")]);
+
+        let config = Config::new(args(&["--max-errors", "3", "in.asm", "out.hack", "--listing", "out.lst"])).unwrap();
+        assert_eq!(config.max_errors, Some(3));
+        assert_eq!(config.listing_file.as_deref(), Some("out.lst"));
+        assert_eq!((config.symbol_file, config.symbol_format), (None, SymbolMapFormat::Text));
+        let config = Config::new(args(&["in.asm", "out.hack", "--sym", "out.sym", "--sym-format", "json"])).unwrap();
+        assert_eq!((config.symbol_file.as_deref(), config.symbol_format), (Some("out.sym"), SymbolMapFormat::Json));
+        assert!(Config::new(args(&["in.asm", "out.hack", "--sym-format", "xml"])).is_err());
+        let config = Config::new(args(&["in.asm", "out.hack", "--debug-info", "out.dbg.json"])).unwrap();
+        assert_eq!(config.debug_info_file.as_deref(), Some("out.dbg.json"));
+        let config = Config::new(args(&["in.asm", "--max-errors", "0", "out.hack"])).unwrap();
+        assert_eq!(config.max_errors, None);
+
+        let config = Config::new(args(&["a.asm", "-o", "prog.hack", "b.asm", "c.asm"])).unwrap();
+        assert_eq!((config.input_files.len(), &config.output_file[..]), (3, "prog.hack"));
+        assert_eq!(Config::new(args(&["disassemble", "in.hack", "-o", "out.asm"])).unwrap().output_file, "out.asm");
+        assert!(Config::new(args(&["a.asm", "b.asm", "prog.hack"])).is_err());
+        assert!(Config::new(args(&["a.asm", "b.asm"])).is_err());
+        assert_eq!(Config::new(args(&["disassemble", "a.hack", "a.asm"])).unwrap().output_file, "a.asm");
+        assert!(Config::new(args(&["disassemble", "a.hack", "b.hack", "-o", "out.asm"])).is_err());
+        assert!(Config::new(args(&["run", "a.asm", "-o", "out.hack"])).is_err());
+        assert!(Config::new(args(&["-o", "prog.hack"])).is_err());
+
+        assert!(Config::new(args(&["in.asm"])).is_err());
+        assert!(Config::new(args(&["in.asm", "out.hack", "--max-errors"])).is_err());
+        assert!(Config::new(args(&["in.asm", "out.hack", "--bogus"])).is_err());
     }
 
     #[test]
-    fn test_instruction_types() {
-        let contents = String::from("\
-@2
-@sum
-D=0
-(END)");
-
-        let mut parser = Parser::create(contents);
-
-        match parser.instruction_type() {
-            Some(Instruction::A) => (),
-            _ => panic!("Expected Instruction::A"),
+    fn test_assembly_errors_capped() {
+        let at = |line, column| Span::new(Rc::from("test.asm"), line, column);
+        let diagnostics = vec![
+            Diagnostic::Error(AssemblyError::DuplicateLabel { label: "L".to_string(), span: at(2, 2) }),
+            Diagnostic::Warning(Warning::RamBudgetExceeded { variable: "x".to_string(), budget: 0, span: at(3, 2) }),
+            Diagnostic::Error(AssemblyError::UnknownComp { comp: "Q".to_string(), span: at(6, 3) }),
+            Diagnostic::Warning(Warning::RamBudgetExceeded { variable: "y".to_string(), budget: 0, span: at(6, 5) }),
+            Diagnostic::Error(AssemblyError::UnknownJump { jump: "JJJ".to_string(), span: at(6, 7) }),
+        ];
+
+        let report = AssemblyErrors::new(diagnostics.clone(), None);
+        assert_eq!(report.diagnostics, diagnostics);
+        assert_eq!(report.omitted, 0);
+
+        let report = AssemblyErrors::new(diagnostics.clone(), Some(2));
+        assert_eq!(report.diagnostics, diagnostics[..4]);
+        assert_eq!(report.omitted, 1);
+        assert_eq!(report.to_string(), "aborting due to 3 previous errors");
+        assert_eq!(report.diagnostics[0].to_string(), "test.asm:2:2: error: duplicate label `L`");
+        assert_eq!(
+            report.diagnostics[1].to_string(),
+            "test.asm:3:2: warning: variable `x` exceeds the RAM budget of 0 words"
+        );
+    }
+
+    fn errors(source: &str) -> Vec<AssemblyError> {
+        match assemble(source) {
+            Ok(_) => panic!("Expected errors"),
+            Err(diagnostics) => diagnostics
+                .into_iter()
+                .filter_map(|diagnostic| match diagnostic {
+                    Diagnostic::Error(err) => Some(err),
+                    Diagnostic::Warning(_) => None,
+                })
+                .collect(),
         }
-        parser.advance();
-        match parser.instruction_type() {
-            Some(Instruction::A) => (),
-            _ => panic!("Expected Instruction::A"),
+    }
+
+    #[test]
+    fn test_assemble() {
+        let program = assemble("\
+// Computes R2 = max(R0, R1)
+   @R0
+   D=M
+   @R1
+   D=D-M
+   @OUTPUT_FIRST
+   D;JGT
+   @R1
+   D=M
+   @OUTPUT_D
+   0;JMP
+(OUTPUT_FIRST)
+   @R0
+   D=M
+(OUTPUT_D)
+   @R2
+   M=D
+(INFINITE_LOOP)
+   @INFINITE_LOOP
+   0;JMP
+   @sum").unwrap();
+
+        assert_eq!(program.words, vec![
+            0x0000, 0xfc10, 0x0001, 0xf4d0, 0x000a, 0xe301, 0x0001, 0xfc10, 0x000c, 0xea87,
+            0x0000, 0xfc10, 0x0002, 0xe308, 0x000e, 0xea87, 0x0010,
+        ]);
+        assert_eq!(program.symbols.get_address("OUTPUT_FIRST"), Some(&10));
+        assert_eq!(program.symbols.get_address("INFINITE_LOOP"), Some(&14));
+        assert_eq!(program.symbols.get_address("sum"), Some(&16));
+        assert_eq!(program.source_map.len(), program.words.len());
+        assert_eq!((program.source_map[10].line, program.source_map[10].column), (13, 5));
+        assert_eq!(&*program.source_map[0].file, "<source>");
+        assert_eq!(program.instructions[..3], ["@R0", "D=M", "@R1"]);
+        assert!(program.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_assemble_reports_all_errors() {
+        let errors: Vec<_> = errors("(A\n@2\nD=Q\n(L)\n(L)\nD=D+X;JJJ\n@1x\n")
+            .iter()
+            .map(|err| (err.span().line, err.span().column))
+            .collect();
+        assert_eq!(errors, vec![(1, 1), (3, 3), (5, 2), (6, 3), (6, 7), (7, 2)]);
+    }
+
+    #[test]
+    fn test_rom_size() {
+        assert!(assemble(&"D=0\n".repeat(ROM_SIZE)).is_ok());
+
+        let source = format!("(START)\n{}", "D=0\n".repeat(ROM_SIZE + 1));
+        match &errors(&source)[..] {
+            [AssemblyError::RomOverflow { size, span }] => {
+                assert_eq!(*size, ROM_SIZE + 1);
+                assert_eq!(span.line, ROM_SIZE + 2);
+            }
+            errors => panic!("Expected a single RomOverflow, got {:?}", errors),
         }
-        parser.advance();
-        match parser.instruction_type() {
-            Some(Instruction::C) => (),
-            _ => panic!("Expected Instruction::C"),
+    }
+
+    #[test]
+    fn test_variable_allocation() {
+        let variables = SCREEN - FIRST_VARIABLE as usize;
+        let source: String = (0..variables).map(|n| format!("@v{}\n", n)).collect();
+        assert!(assemble(&source).is_ok());
+
+        let source = format!("{}@screen_var\n@v0\n@kbd_var\n@screen_var\n", source);
+        match &errors(&source)[..] {
+            [AssemblyError::RamOverflow { variable, address, .. }, AssemblyError::RamOverflow { variable: next, .. }] => {
+                assert_eq!((&variable[..], *address), ("screen_var", SCREEN as i32));
+                assert_eq!(next, "kbd_var");
+            }
+            errors => panic!("Expected two RamOverflow errors, got {:?}", errors),
         }
-        parser.advance();
-        match parser.instruction_type() {
-            Some(Instruction::L) => (),
-            _ => panic!("Expected Instruction::L"),
+    }
+
+    #[test]
+    fn test_ram_budget_warning() {
+        let source = "@a\n@b\n@a\n@c\n@d\n";
+        assert_eq!(assemble(source).unwrap().warnings, vec![]);
+
+        let options = Options { ram_budget: Some(2), ..Options::default() };
+        let program = assemble_source(source, "test.asm", &options).unwrap();
+        match &program.warnings[..] {
+            [Warning::RamBudgetExceeded { variable, budget, span }] => {
+                assert_eq!((&variable[..], *budget, span.line), ("c", 2, 4));
+            }
+            warnings => panic!("Expected a single RamBudgetExceeded, got {:?}", warnings),
         }
     }
 
     #[test]
-    fn test_instruction_symbols() {
-        let contents = String::from("\
-@2
-@sum
-D=0
-@R2
-@R15
-@SCREEN
-@KBD
-@SP
-@LCL
-@ARG
-@THIS
-@THAT
-(END)");
-
-        let mut parser = Parser::create(contents);
-
-        assert_eq!(parser.symbol(), Some("2".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("sum".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), None);
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("2".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("15".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("16384".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("24576".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("0".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("1".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("2".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("3".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("4".to_string()));
-        parser.advance();
-        assert_eq!(parser.symbol(), Some("END".to_string()));
+    fn test_expressions() {
+        let program = assemble("@ARR\n(ARR)\n@ARR+5\n@SCREEN+32*2\n@'A'\n@0x4000\n@i\n@i+1\n").unwrap();
+        assert_eq!(program.words, vec![1, 6, 16448, 65, 16384, 16, 17]);
+        assert_eq!(assemble("@SCREEN + 32\nD = A\n").unwrap().instructions, ["@SCREEN + 32", "D=A"]);
+
+        let errors: Vec<_> = errors("@i+1\n@i\n@1-END\n@1/0\n@(1\n(END)\n").iter().map(|err| err.to_string()).collect();
+        assert_eq!(errors, vec![
+            "symbol `i` is not defined before it is used in an expression",
+            "constant `1-END` is out of range, expected 0..=32767",
+            "division by zero",
+            "bad A-instruction operand `(1`",
+        ]);
     }
 
     #[test]
-    fn test_dest_comp_jump() {
-        let contents = String::from("\
-@2
-@sum
-D=0
-D=D+1;JLE
-D;JGT
-(END)");
-
-        let mut parser = Parser::create(contents);
-
-        assert!(parser.dest().is_none() && parser.comp().is_none() && parser.jump().is_none());
-        parser.advance();
-        assert!(parser.dest().is_none() && parser.comp().is_none() && parser.jump().is_none());
-        parser.advance();
-        assert_eq!(parser.dest(), Some("D".to_string()));
-        assert_eq!(parser.comp(), Some("0".to_string()));
-        assert_eq!(parser.jump(), None);
-        parser.advance();
-        assert_eq!(parser.dest(), Some("D".to_string()));
-        assert_eq!(parser.comp(), Some("D+1".to_string()));
-        assert_eq!(parser.jump(), Some("JLE".to_string()));
-        parser.advance();
-        assert_eq!(parser.dest(), None);
-        assert_eq!(parser.comp(), Some("D".to_string()));
-        assert_eq!(parser.jump(), Some("JGT".to_string()));
-        parser.advance();
-        assert!(parser.dest().is_none() && parser.comp().is_none() && parser.jump().is_none());
+    fn test_constants() {
+        let program = assemble(".equ SIZE 512\n(ROW = 32)\n@SIZE\n@SCREEN+ROW*2\n@i\n(END)\n.equ LAST END-1\n@LAST\n").unwrap();
+        assert_eq!(program.words, vec![512, 16448, 16, 2]);
+        assert_eq!(program.symbols.kind("SIZE"), Some(SymbolKind::Constant));
+        assert_eq!(program.symbols.get_address("i"), Some(&16));
+
+        let errors: Vec<_> = errors(".equ A 1\n.equ A 2\n(B)\n.equ B 3\n.equ R0 0\n.equ C D+1\n(D)\n.equ E 1-2\n")
+            .iter()
+            .map(|err| err.to_string())
+            .collect();
+        assert_eq!(errors, vec![
+            "constant `A` is already defined",
+            "constant `B` is already defined",
+            "constant `R0` is already defined",
+            "symbol `D` is not defined before it is used in an expression",
+            "constant `1-2` is out of range, expected 0..=32767",
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "Invalid dest")]
-    fn test_code_dest() {
-        assert_eq!(Code::dest(None), "000");
-        assert_eq!(Code::dest(Some(String::from("M"))), "001");
-        assert_eq!(Code::dest(Some(String::from("D"))), "010");
-        assert_eq!(Code::dest(Some(String::from("DM"))), "011");
-        assert_eq!(Code::dest(Some(String::from("A"))), "100");
-        assert_eq!(Code::dest(Some(String::from("AM"))), "101");
-        assert_eq!(Code::dest(Some(String::from("AD"))), "110");
-        assert_eq!(Code::dest(Some(String::from("ADM"))), "111");
+    fn test_macros() {
+        let source = "\
+.macro ADD_TO target, value
+   @value
+   D=A
+   @target
+   M=D+M
+.endm
+   ADD_TO sum 3
+   ADD_TO sum 0x8000
+";
+        match assemble_source(source, "test.asm", &Options::default()) {
+            Err(diagnostics) => {
+                let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+                assert_eq!(messages, vec!["\
+test.asm:2:5: error: constant `0x8000` is out of range, expected 0..=32767
+test.asm:8:4: note: in expansion of macro `ADD_TO`"]);
+            }
+            Ok(_) => panic!("Expected errors"),
+        }
 
-        // panic
-        Code::dest(Some(String::from("ELMO")));
+        let program = assemble(&source.replace("0x8000", "2")).unwrap();
+        assert_eq!(program.words.len(), 8);
+        assert_eq!(program.words[4..], [2, 0xec10, 16, 0xf088]);
+        assert_eq!(program.source_map[4].call_site().line, 8);
+        assert_eq!(program.instructions[4..], ["@2", "D=A", "@sum", "M=D+M"]);
     }
 
     #[test]
-    #[should_panic(expected = "Invalid jump condition")]
-    fn test_code_jump() {
-        assert_eq!(Code::jump(None), "000");
-        assert_eq!(Code::jump(Some(String::from("JGT"))), "001");
-        assert_eq!(Code::jump(Some(String::from("JEQ"))), "010");
-        assert_eq!(Code::jump(Some(String::from("JGE"))), "011");
-        assert_eq!(Code::jump(Some(String::from("JLT"))), "100");
-        assert_eq!(Code::jump(Some(String::from("JNE"))), "101");
-        assert_eq!(Code::jump(Some(String::from("JLE"))), "110");
-        assert_eq!(Code::jump(Some(String::from("JMP"))), "111");
+    fn test_assemble_files() {
+        let file = |name: &str, text: &str| Source { file: Rc::from(name), text: text.to_string() };
+        let files = [file("a.asm", "@MAIN\n0;JMP\n(LOOP)\n@LOOP\n"), file("b.asm", "(MAIN)\n@LOOP\n0;JMP\n")];
+        let program = assemble_files(&files, &Options::default()).unwrap();
+        assert_eq!(program.words, vec![3, 0xea87, 2, 2, 0xea87]);
+        assert_eq!(&*program.source_map[3].file, "b.asm");
+
+        let files = [file("a.asm", "(LOOP)\nD=Q\n"), file("b.asm", "D=0\n(LOOP)\n")];
+        match assemble_files(&files, &Options::default()) {
+            Err(diagnostics) => {
+                let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+                assert_eq!(messages, vec![
+                    "a.asm:2:3: error: unknown comp `Q`",
+                    "b.asm:2:2: error: duplicate label `LOOP`",
+                ]);
+            }
+            Ok(_) => panic!("Expected errors"),
+        }
+    }
 
-        // panic
-        Code::jump(Some(String::from("ELMO")));
+    #[test]
+    fn test_assemble_without_includes() {
+        // the file exists, but assembling a string must not read it
+        let dir = TempDir::new("test_assemble_without_includes");
+        fs::write(dir.join("x.asm"), "@1\n").unwrap();
+        let source = format!(".include \"x\"\n.include \"{}\"\n", dir.join("x.asm").display());
+
+        match assemble(&source) {
+            Err(diagnostics) => {
+                let messages: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+                assert_eq!(messages, vec![
+                    "<source>:1:1: error: can't include `x`: includes are not enabled".to_string(),
+                    format!("<source>:2:1: error: can't include `{}`: includes are not enabled", dir.join("x.asm").display()),
+                ]);
+            }
+            Ok(_) => panic!("Expected errors"),
+        }
     }
 
     #[test]
-    fn test_code_comp() {
-        assert_eq!(Code::comp(Some(String::from("0"))), "0101010");
-        assert_eq!(Code::comp(Some(String::from("1"))), "0111111");
-        assert_eq!(Code::comp(Some(String::from("-1"))), "0111010");
-        assert_eq!(Code::comp(Some(String::from("D"))), "0001100");
-        assert_eq!(Code::comp(Some(String::from("A"))), "0110000");
-        assert_eq!(Code::comp(Some(String::from("!D"))), "0001101");
-        assert_eq!(Code::comp(Some(String::from("!A"))), "0110011");
-        assert_eq!(Code::comp(Some(String::from("-D"))), "0001111");
-        assert_eq!(Code::comp(Some(String::from("-A"))), "0110011");
-        assert_eq!(Code::comp(Some(String::from("D+1"))), "0011111");
-        assert_eq!(Code::comp(Some(String::from("A+1"))), "0110111");
-        assert_eq!(Code::comp(Some(String::from("D-1"))), "0001110");
-        assert_eq!(Code::comp(Some(String::from("A-1"))), "0110010");
-        assert_eq!(Code::comp(Some(String::from("D+A"))), "0000010");
-        assert_eq!(Code::comp(Some(String::from("D-A"))), "0010011");
-        assert_eq!(Code::comp(Some(String::from("A-D"))), "0000111");
-        assert_eq!(Code::comp(Some(String::from("D&A"))), "0000000");
-        assert_eq!(Code::comp(Some(String::from("D|A"))), "0010101");
-        assert_eq!(Code::comp(Some(String::from("M"))), "1110000");
-        assert_eq!(Code::comp(Some(String::from("!M"))), "1110001");
-        assert_eq!(Code::comp(Some(String::from("-M"))), "1110011");
-        assert_eq!(Code::comp(Some(String::from("M+1"))), "1110111");
-        assert_eq!(Code::comp(Some(String::from("M-1"))), "1110010");
-        assert_eq!(Code::comp(Some(String::from("D+M"))), "1000010");
-        assert_eq!(Code::comp(Some(String::from("D-M"))), "1010011");
-        assert_eq!(Code::comp(Some(String::from("M-D"))), "1000111");
-        assert_eq!(Code::comp(Some(String::from("D&M"))), "1000000");
-        assert_eq!(Code::comp(Some(String::from("D|M"))), "1010101");
+    fn test_lint() {
+        let source = "AM=M+1\nMA=1+M\nD=D|M\nDM=M|D;JMP\n";
+        assert_eq!(assemble(source).unwrap().warnings, vec![]);
+
+        let options = Options { lint: true, ..Options::default() };
+        let program = assemble_source(source, "test.asm", &options).unwrap();
+        assert_eq!(program.words, assemble("AM=M+1\nAM=M+1\nD=D|M\nMD=D|M;JMP\n").unwrap().words);
+        let warnings: Vec<_> = program
+            .warnings
+            .iter()
+            .map(|warning| (warning.span().line, warning.span().column, warning.to_string()))
+            .collect();
+        assert_eq!(warnings, vec![
+            (2, 1, "`MA` is usually written `AM`".to_string()),
+            (2, 4, "`1+M` is usually written `M+1`".to_string()),
+            (4, 1, "`DM` is usually written `MD`".to_string()),
+            (4, 4, "`M|D` is usually written `D|M`".to_string()),
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "No comp provided")]
-    fn test_code_comp_panic1() {
-        Code::comp(None);
+    fn test_label_out_of_range() {
+        // the ROM is full, so END is one past its last address
+        let source = format!("@END\n{}(END)\n", "D=0\n".repeat(ROM_SIZE - 1));
+        match &errors(&source)[..] {
+            [AssemblyError::ConstantOutOfRange { value, span }] => {
+                assert_eq!((&value[..], span.line), ("END", 1));
+            }
+            errors => panic!("Unexpected errors: {:?}", errors),
+        }
     }
 
     #[test]
-    #[should_panic(expected = "Invalid comp")]
-    fn test_code_comp_panic2() {
-        Code::comp(Some(String::from("ELMO")));
+    fn test_hack_text() {
+        let config = Config::new(args(&["in.asm", "out.hack"])).unwrap();
+        let assembler = HackAssembler::new(&config, vec![0x0002, 0xec10, 0xffff], Vec::new());
+        assert_eq!(assembler.to_text(), "0000000000000010\n1110110000010000\n1111111111111111\n");
     }
 
     #[test]
-    fn test_symboltable() {
-        let mut symbols = SymbolTable::new();
+    fn test_parse_hack() {
+        assert_eq!(parse_hack("0000000000000010\n\n  1110110000010000\r\n"), Ok(vec![0x0002, 0xec10]));
+        assert_eq!(
+            parse_hack("0000000000000010\n111011000001000\n"),
+            Err(FormatError { line: 2, message: "expected 16 binary digits, found `111011000001000`".to_string() })
+        );
+        assert!(parse_hack("000000000000001x").is_err());
+    }
 
-        assert!(!symbols.contains("END"));
-        symbols.add_entry("END".to_string(), 123);
-        assert!(symbols.contains("END"));
-        assert_eq!(symbols.get_address("END"), Some(&123));
-        assert_eq!(symbols.get_address("START"), None);
+    #[test]
+    fn test_parse_machine_code() {
+        let words = vec![0x0002, 0xec10];
+        assert_eq!(parse_machine_code("0000000000000010\n1110110000010000\n", Endian::Big), Ok(words.clone()));
+        assert_eq!(parse_machine_code("\n:040000000002EC10FE\n:00000001FF\n", Endian::Big), Ok(words.clone()));
+        assert_eq!(parse_machine_code("S10700000002EC10FA\nS9030000FC\n", Endian::Big), Ok(words));
+        assert!(is_machine_code(Path::new("prog.s19")));
+        assert!(!is_machine_code(Path::new("prog.asm")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_run() {
+        let dir = TempDir::new("test_run");
+        let input = dir.join("prog.asm");
+        let output = dir.join("prog.hack");
+        fs::write(&input, "@2\nD=A\n(LOOP)\n@LOOP\n0;JMP\n").unwrap();
+
+        let config = Config::new(args(&[input.to_str().unwrap(), output.to_str().unwrap()])).unwrap();
+        assert_eq!(run(config).unwrap(), vec![]);
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "0000000000000010\n1110110000010000\n0000000000000010\n1110101010000111\n"
+        );
+
+        fs::write(&input, "@2\nD=Q\n").unwrap();
+        let config = Config::new(args(&[input.to_str().unwrap(), output.to_str().unwrap()])).unwrap();
+        let report = run(config).unwrap_err().downcast::<AssemblyErrors>().unwrap();
+        assert_eq!(report.diagnostics.len(), 1);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::error::Span;
+use crate::symbol_table::SymbolKind;
+use crate::Program;
+
+/// Renders the source next to the ROM address and machine code of each
+/// instruction, followed by the labels and variables it defines. Label
+/// definitions are shown with the address they resolve to. The code of a
+/// macro call or include is listed below it, next to the line `n+` it
+/// came from. Several input files are listed one after the other.
+pub fn listing(program: &Program) -> String {
+    let mut code: HashMap<(&str, usize), Vec<(usize, &Span)>> = HashMap::new();
+    for (address, span) in program.source_map.iter().enumerate() {
+        let origin = span.origin();
+        code.entry((&origin.file, origin.line)).or_default().push((address, span));
+    }
+
+    let mut symbols: Vec<_> = program
+        .symbols
+        .iter()
+        .filter_map(|(name, address)| Some((name, address, program.symbols.definition(name)?)))
+        .collect();
+    symbols.sort_by_key(|&(name, _, _)| name);
+
+    let labels: HashMap<(&str, usize), i32> = symbols
+        .iter()
+        .filter(|&&(name, _, span)| program.symbols.kind(name) == Some(SymbolKind::Label) && std::ptr::eq(span.origin(), span))
+        .map(|&(_, address, span)| ((&*span.file, span.line), address))
+        .collect();
+
+    // every file split once, for the lines of macro bodies and included files
+    let lines: HashMap<&str, Vec<&str>> = program
+        .sources
+        .iter()
+        .chain(&program.included)
+        .map(|source| (&*source.file, source.text.lines().collect()))
+        .collect();
+
+    let mut text = format!("{:>5}  {:>4}  {:16}  {:>5}  {}\n", "addr", "hex", "word", "line", "source");
+    let word = |address: usize| format!("{:>5}  {:04X}  {:016b}", address, address, program.words[address]);
+    let address_only = |address| format!("{:>5}  {:04X}  {:16}", address, address, "");
+
+    for source in &program.sources {
+        if program.sources.len() > 1 {
+            writeln!(text, "\n{}:", source.file).unwrap();
+        }
+
+        for (index, line) in lines[&*source.file].iter().enumerate() {
+            let key = (&*source.file, index + 1);
+            let (prefix, expanded) = match (code.get(&key).map(Vec::as_slice), labels.get(&key)) {
+                (Some(&[(address, span)]), _) if std::ptr::eq(span.origin(), span) => (word(address), &[][..]),
+                (Some(expanded), _) => (address_only(expanded[0].0), expanded),
+                (None, Some(&address)) => (address_only(address as usize), &[][..]),
+                (None, None) => (format!("{:5}  {:4}  {:16}", "", "", ""), &[][..]),
+            };
+            writeln!(text, "{}", format!("{}  {:>5}  {}", prefix, index + 1, line).trim_end()).unwrap();
+
+            for &(address, span) in expanded {
+                let body = lines.get(&*span.file).and_then(|lines| lines.get(span.line - 1)).unwrap_or(&"");
+                writeln!(text, "{}", format!("{}  {:>4}+  {}", word(address), span.line, body).trim_end()).unwrap();
+            }
+        }
+    }
+
+    // with several files a line number alone doesn't say where a symbol is
+    let several_files = program.sources.len() + program.included.len() > 1;
+    let file_header = if several_files { "  file" } else { "" };
+    writeln!(text, "\nSymbols:\n {:<24} {:>5}  {:>4}  {:>5}{}", "name", "addr", "hex", "line", file_header).unwrap();
+    for (name, address, span) in symbols {
+        let call_site = span.call_site();
+        write!(text, " {:<24} {:>5}  {:04X}  {:>5}", name, address, address, call_site.line).unwrap();
+        if several_files {
+            write!(text, "  {}", call_site.file).unwrap();
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, assemble_files, Options, Source};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_listing() {
+        let source = "\
+// Adds 1 to sum until it is 10
+
+(LOOP)
+   @sum    // the variable
+   M=M+1
+   @LOOP
+   0;JMP";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(listing(&program), " addr   hex  word               line  source
+                                   1  // Adds 1 to sum until it is 10
+                                   2
+    0  0000                        3  (LOOP)
+    0  0000  0000000000010000      4     @sum    // the variable
+    1  0001  1111110111001000      5     M=M+1
+    2  0002  0000000000000000      6     @LOOP
+    3  0003  1110101010000111      7     0;JMP
+
+Symbols:
+ name                      addr   hex   line
+ LOOP                         0  0000      3
+ sum                         16  0010      4
+");
+    }
+
+    #[test]
+    fn test_listing_macros() {
+        let source = "\
+.macro INC var
+   @var
+   M=M+1
+.endm
+   INC i
+   D=0";
+        let program = assemble(source).unwrap();
+
+        assert!(listing(&program).starts_with(" addr   hex  word               line  source
+                                   1  .macro INC var
+                                   2     @var
+                                   3     M=M+1
+                                   4  .endm
+    0  0000                        5     INC i
+    0  0000  0000000000010000     2+     @var
+    1  0001  1111110111001000     3+     M=M+1
+    2  0002  1110101010010000      6     D=0
+"));
+    }
+
+    #[test]
+    fn test_listing_files() {
+        let file = |name: &str, text: &str| Source { file: Rc::from(name), text: text.to_string() };
+        let files = [file("a.asm", "@MAIN\n0;JMP\n"), file("b.asm", "(MAIN)\n@i\n")];
+        let program = assemble_files(&files, &Options::default()).unwrap();
+
+        assert!(listing(&program).ends_with("
+Symbols:
+ name                      addr   hex   line  file
+ MAIN                         2  0002      1  b.asm
+ i                           16  0010      2  b.asm
+"));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::error::{AssemblyError, Expansion};
+use crate::lexer::{Token, TokenKind};
+use crate::parser::{is_directive, separated, source_text};
+
+struct Macro {
+    params: Vec<String>,
+    /// Lines of the body, each ending with its `Newline` token.
+    body: Vec<Vec<Token>>,
+    /// Labels defined in the body, renamed for every expansion.
+    labels: Vec<String>,
+}
+
+/// Removes the `.macro NAME params ... .endm` definitions from the lines
+/// and replaces every call `NAME args` by the body of the macro, with the
+/// parameters substituted. Arguments are separated by commas, or by
+/// whitespace in calls without commas.
+/// Labels defined in a body become `NAME.n$LABEL` in the nth expansion.
+/// Every line ends with its `Newline` token.
+pub fn expand(lines: Vec<Vec<Token>>) -> (Vec<Vec<Token>>, Vec<AssemblyError>) {
+    let mut expander = Expander { macros: HashMap::new(), active: Vec::new(), expansions: 0, errors: Vec::new() };
+    let lines = expander.collect_definitions(lines);
+
+    let mut expanded = Vec::new();
+    for line in lines {
+        expander.expand_line(line, &mut expanded);
+    }
+
+    (expanded, expander.errors)
+}
+
+struct Expander {
+    macros: HashMap<String, Rc<Macro>>,
+    /// Macros being expanded, outermost first.
+    active: Vec<String>,
+    /// Number of expansions so far, used to make labels unique.
+    expansions: usize,
+    errors: Vec<AssemblyError>,
+}
+
+impl Expander {
+    /// Records the macro definitions, returning the lines outside of them.
+    fn collect_definitions(&mut self, lines: Vec<Vec<Token>>) -> Vec<Vec<Token>> {
+        let mut rest = Vec::new();
+        let mut lines = lines.into_iter();
+
+        while let Some(line) = lines.next() {
+            if !is_directive(&line, ".macro") {
+                if is_directive(&line, ".endm") {
+                    self.malformed(&line);
+                } else {
+                    rest.push(line);
+                }
+                continue;
+            }
+
+            let mut body = Vec::new();
+            let mut terminated = false;
+            for line in lines.by_ref() {
+                if is_directive(&line, ".endm") {
+                    terminated = true;
+                    break;
+                }
+                if is_directive(&line, ".macro") {
+                    // definitions can't be nested
+                    self.malformed(&line);
+                } else {
+                    body.push(line);
+                }
+            }
+
+            let header = &line[..line.len() - 1];
+            let params: Option<Vec<String>> = header
+                .iter()
+                .skip(1)
+                .filter(|token| token.kind != TokenKind::Comma)
+                .map(|token| match &token.kind {
+                    TokenKind::Identifier(name) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect();
+
+            let (name, span, params) = match (params, header.get(1)) {
+                (Some(mut params), Some(token @ Token { kind: TokenKind::Identifier(_), .. })) => {
+                    (params.remove(0), token.span.clone(), params)
+                }
+                _ => {
+                    self.malformed(&line);
+                    continue;
+                }
+            };
+
+            if !terminated {
+                self.errors.push(AssemblyError::UnterminatedMacro { name, span });
+                continue;
+            }
+
+            match self.macros.entry(name) {
+                Entry::Occupied(entry) => {
+                    self.errors.push(AssemblyError::DuplicateMacro { name: entry.key().clone(), span });
+                }
+                Entry::Vacant(entry) => {
+                    let labels = body
+                        .iter()
+                        .filter_map(|line| match &line[..] {
+                            [Token { kind: TokenKind::LParen, .. }, Token { kind: TokenKind::Identifier(label), .. }, Token { kind: TokenKind::RParen, .. }, _] => {
+                                Some(label.clone())
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    entry.insert(Rc::new(Macro { params, body, labels }));
+                }
+            }
+        }
+
+        rest
+    }
+
+    fn malformed(&mut self, line: &[Token]) {
+        let definition = source_text(&line[..line.len() - 1]);
+        self.errors.push(AssemblyError::MalformedMacro { definition, span: line[0].span.clone() });
+    }
+
+    /// Appends the line to `out`, or its expansion if it calls a macro.
+    /// Returns false if a macro calls itself, directly or not, which
+    /// abandons the expansion of the outermost call.
+    fn expand_line(&mut self, line: Vec<Token>, out: &mut Vec<Vec<Token>>) -> bool {
+        // `NAME=...` and `NAME;...` are C-instructions even if a macro is called NAME
+        let called = match (&line[0].kind, line.get(1).map(|token| &token.kind)) {
+            (_, Some(TokenKind::Equals | TokenKind::Semicolon)) => None,
+            (TokenKind::Identifier(name), _) => self.macros.get(name).map(|called| (name.clone(), called.clone())),
+            _ => None,
+        };
+        let Some((name, called)) = called else {
+            out.push(line);
+            return true;
+        };
+
+        let call = line[0].span.clone();
+        let args = arguments(&line[1..line.len() - 1]);
+        if args.len() != called.params.len() {
+            let (expected, found) = (called.params.len(), args.len());
+            self.errors.push(AssemblyError::MacroArguments { name, expected, found, span: call });
+            return true;
+        }
+        if self.active.contains(&name) {
+            self.errors.push(AssemblyError::MacroRecursion { name, span: call });
+            return false;
+        }
+
+        self.expansions += 1;
+        let number = self.expansions;
+        let expansion = Rc::new(Expansion { name: name.clone(), call });
+        self.active.push(name.clone());
+        let start = out.len();
+
+        for body_line in &called.body {
+            let mut expanded = Vec::new();
+            for token in body_line {
+                let mut span = token.span.clone();
+                span.expansion = Some(expansion.clone());
+
+                let kind = match &token.kind {
+                    TokenKind::Identifier(ident) => {
+                        // arguments take the place of the parameter, span included
+                        if let Some(index) = called.params.iter().position(|param| param == ident) {
+                            let arg = args[index].iter().map(|arg| Token { kind: arg.kind.clone(), span: span.clone() });
+                            expanded.extend(arg);
+                            continue;
+                        }
+                        if called.labels.contains(ident) {
+                            TokenKind::Identifier(format!("{}.{}${}", name, number, ident))
+                        } else {
+                            token.kind.clone()
+                        }
+                    }
+                    kind => kind.clone(),
+                };
+                expanded.push(Token { kind, span });
+            }
+            if !self.expand_line(expanded, out) {
+                self.active.pop();
+                out.truncate(start);
+                return self.active.is_empty();
+            }
+        }
+
+        self.active.pop();
+        true
+    }
+}
+
+/// Splits the tokens of a macro call into arguments at commas, or at
+/// whitespace if there are none, so `1 + 2, x` is two arguments.
+fn arguments(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.iter().any(|token| token.kind == TokenKind::Comma) {
+        return tokens.split(|token| token.kind == TokenKind::Comma).filter(|arg| !arg.is_empty()).collect();
+    }
+
+    let mut args = Vec::new();
+    let mut start = 0;
+
+    for (index, token) in tokens.iter().enumerate().skip(1) {
+        if separated(&tokens[index - 1], token) {
+            args.push(&tokens[start..index]);
+            start = index;
+        }
+    }
+    if start < tokens.len() {
+        args.push(&tokens[start..]);
+    }
+
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::split_lines;
+
+    fn expand_source(source: &str) -> (Vec<String>, Vec<String>) {
+        let (lines, errors) = expand(split_lines(tokenize(source, "test.asm")));
+
+        let lines = lines.iter().map(|line| source_text(&line[..line.len() - 1])).collect();
+        let errors = errors.iter().map(|err| format!("{}: {}", err.span().line, err)).collect();
+        (lines, errors)
+    }
+
+    #[test]
+    fn test_expand() {
+        let (lines, errors) = expand_source("\
+.macro INC var
+  @var
+  M=M+1
+.endm
+.macro COUNT_DOWN counter, target
+(LOOP)
+  INC target
+  @counter
+  MD=M-1
+  @LOOP
+  D;JGT
+.endm
+COUNT_DOWN R13 SCREEN+1
+COUNT_DOWN i, j
+D=0");
+
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(lines, vec![
+            "(COUNT_DOWN.1$LOOP)",
+            "@SCREEN+1",
+            "M=M+1",
+            "@R13",
+            "MD=M-1",
+            "@COUNT_DOWN.1$LOOP",
+            "D;JGT",
+            "(COUNT_DOWN.3$LOOP)",
+            "@j",
+            "M=M+1",
+            "@i",
+            "MD=M-1",
+            "@COUNT_DOWN.3$LOOP",
+            "D;JGT",
+            "D=0",
+        ]);
+    }
+
+    #[test]
+    fn test_expand_comma_arguments() {
+        let (lines, errors) = expand_source(".macro ADD target, value\n  @value\n  D=A\n  @target\n  M=D+M\n.endm\nADD sum, (1 + 2) * 3\n");
+
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(lines, vec!["@(1+2)*3", "D=A", "@sum", "M=D+M"]);
+    }
+
+    #[test]
+    fn test_expansion_spans() {
+        let (lines, _) = expand(split_lines(tokenize(".macro SET x\n  @x\n  M=1\n.endm\n\nSET i\n", "test.asm")));
+
+        let at = &lines[1][0].span;
+        assert_eq!((at.line, at.column), (2, 3));
+        let expansion = at.expansion.as_ref().unwrap();
+        assert_eq!((&expansion.name[..], expansion.call.line, expansion.call.column), ("SET", 6, 1));
+        assert_eq!(lines[1][1].kind, TokenKind::Identifier("i".to_string()));
+        assert_eq!((lines[1][1].span.line, lines[1][1].span.column), (2, 4));
+        assert_eq!(lines[2][0].span.call_site().line, 6);
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let (lines, errors) = expand_source("\
+.macro
+.endm
+.macro , a
+.endm
+.macro TWO a b
+  @a
+.endm
+.macro TWO
+.endm
+TWO 1
+.macro SELF
+  SELF
+  SELF
+.endm
+.macro PING
+  D=0
+  PONG
+.endm
+.macro PONG
+  PING
+.endm
+SELF
+PONG
+.macro OPEN
+  .macro INNER
+  D=0");
+
+        assert_eq!(lines, Vec::<String>::new());
+        assert_eq!(errors, vec![
+            "1: malformed macro definition `.macro`",
+            "3: malformed macro definition `.macro , a`",
+            "8: macro `TWO` is already defined",
+            "25: malformed macro definition `.macro INNER`",
+            "24: macro `OPEN` has no `.endm`",
+            "10: macro `TWO` expects 2 arguments, found 1",
+            "12: macro `SELF` calls itself",
+            "17: macro `PONG` calls itself",
+        ]);
+    }
+}
@@ -1,7 +1,7 @@
 use std::env;
 use std::process;
 
-use hack_assembler::Config;
+use hack_assembler::{AssemblyErrors, Config, Diagnostic};
 
 fn main() {
     let config = Config::new(env::args()).unwrap_or_else(|err| {
@@ -9,8 +9,26 @@ fn main() {
         process::exit(1);
     });
 
-    if let Err(err) = hack_assembler::run(config) {
-        eprintln!("Application error: {}", err);
-        process::exit(1);
+    match hack_assembler::run(config) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("{}", Diagnostic::Warning(warning));
+            }
+        }
+        Err(err) => {
+            match err.downcast_ref::<AssemblyErrors>() {
+                Some(report) => {
+                    for diagnostic in &report.diagnostics {
+                        eprintln!("{}", diagnostic);
+                    }
+                    if report.omitted > 0 {
+                        eprintln!("note: {} more errors not shown", report.omitted);
+                    }
+                    eprintln!("error: {}", report);
+                }
+                None => eprintln!("Application error: {}", err),
+            }
+            process::exit(1);
+        }
     }
 }
@@ -0,0 +1,8 @@
+/// Number of words in the data memory, including the memory maps.
+pub const RAM_SIZE: usize = 32768;
+/// Number of words in the instruction memory.
+pub const ROM_SIZE: usize = 32768;
+/// Start of the 8K screen memory map.
+pub const SCREEN: usize = 16384;
+/// Address of the keyboard register.
+pub const KBD: usize = 24576;
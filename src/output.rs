@@ -0,0 +1,606 @@
+use std::fmt::Write;
+
+use crate::memory::ROM_SIZE;
+use crate::{FormatError, HackAssembler};
+
+/// Data bytes per record in Intel HEX and S-record output.
+pub const DEFAULT_RECORD_LENGTH: usize = 16;
+
+/// File format of the assembled program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One line of 16 binary digits per word.
+    #[default]
+    Hack,
+    /// Packed 16-bit words.
+    Binary,
+    /// Intel HEX records.
+    IntelHex,
+    /// Motorola S-records.
+    SRecord,
+    /// Verilog `$readmemh` file, one hex word per line.
+    ReadMemH,
+    /// Verilog `$readmemb` file, one binary word per line.
+    ReadMemB,
+    /// Xilinx memory initialization file.
+    Coe,
+    /// Intel/Altera memory initialization file.
+    Mif,
+    /// VHDL package with the program as a constant array.
+    Vhdl,
+    /// Logisim-evolution `v2.0 raw` image with run-length compression.
+    Logisim,
+    /// Digital `.hex` image, one word per line.
+    Digital,
+    /// C array `static const uint16_t prog[]`.
+    C,
+    /// Rust array `pub static PROGRAM: [u16; N]`.
+    Rust,
+    /// Python list `PROGRAM`.
+    Python,
+}
+
+impl OutputFormat {
+    /// Parses the name used by `--format`.
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "hack" => Some(OutputFormat::Hack),
+            "bin" => Some(OutputFormat::Binary),
+            "ihex" => Some(OutputFormat::IntelHex),
+            "srec" => Some(OutputFormat::SRecord),
+            "memh" => Some(OutputFormat::ReadMemH),
+            "memb" => Some(OutputFormat::ReadMemB),
+            "coe" => Some(OutputFormat::Coe),
+            "mif" => Some(OutputFormat::Mif),
+            "vhdl" => Some(OutputFormat::Vhdl),
+            "logisim" => Some(OutputFormat::Logisim),
+            "digital" => Some(OutputFormat::Digital),
+            "c" => Some(OutputFormat::C),
+            "rust" => Some(OutputFormat::Rust),
+            "python" => Some(OutputFormat::Python),
+            _ => None,
+        }
+    }
+}
+
+/// Byte order of words in binary output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub endian: Endian,
+    /// Number of words to pad the image to.
+    pub rom_size: Option<usize>,
+    /// Word the padding is filled with.
+    pub fill: u16,
+    /// Data bytes per Intel HEX or S-record record.
+    pub record_length: usize,
+    /// Whether source arrays have the instruction text next to each word.
+    pub comments: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            format: OutputFormat::default(),
+            endian: Endian::default(),
+            rom_size: None,
+            fill: 0,
+            record_length: DEFAULT_RECORD_LENGTH,
+            comments: false,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Pads the words to `rom_size` with `fill`.
+    pub fn pad(&self, words: &mut Vec<u16>) -> Result<(), String> {
+        if let Some(size) = self.rom_size {
+            if words.len() > size {
+                return Err(format!("program has {} words, but the ROM size is {}", words.len(), size));
+            }
+            words.resize(size, self.fill);
+        }
+        Ok(())
+    }
+}
+
+impl HackAssembler {
+    /// Contents of the output file in the selected format.
+    pub(crate) fn render(&self) -> Vec<u8> {
+        match self.output.format {
+            OutputFormat::Hack => self.to_text().into_bytes(),
+            OutputFormat::Binary => self.to_binary(),
+            OutputFormat::IntelHex => self.to_intel_hex().into_bytes(),
+            OutputFormat::SRecord => self.to_srecord().into_bytes(),
+            OutputFormat::ReadMemH => self.to_readmemh().into_bytes(),
+            // $readmemb reads the same lines as the .hack format
+            OutputFormat::ReadMemB => self.to_text().into_bytes(),
+            OutputFormat::Coe => self.to_coe().into_bytes(),
+            OutputFormat::Mif => self.to_mif().into_bytes(),
+            OutputFormat::Vhdl => self.to_vhdl().into_bytes(),
+            OutputFormat::Logisim => self.to_logisim().into_bytes(),
+            OutputFormat::Digital => format!("v2.0 raw\n{}", self.to_readmemh()).into_bytes(),
+            OutputFormat::C => {
+                let open = format!("#include <stdint.h>\n\nstatic const uint16_t prog[{}] = {{\n", self.words.len());
+                self.to_array(&open, "};\n", " //").into_bytes()
+            }
+            OutputFormat::Rust => {
+                let open = format!("pub static PROGRAM: [u16; {}] = [\n", self.words.len());
+                self.to_array(&open, "];\n", " //").into_bytes()
+            }
+            OutputFormat::Python => self.to_array("PROGRAM = [\n", "]\n", "  #").into_bytes(),
+        }
+    }
+
+    /// Renders the words as two bytes each, in the selected byte order.
+    fn to_binary(&self) -> Vec<u8> {
+        self.words
+            .iter()
+            .flat_map(|word| match self.output.endian {
+                Endian::Big => word.to_be_bytes(),
+                Endian::Little => word.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    /// Renders the binary image as Intel HEX data records. The ROM is at
+    /// most 64K bytes, so 16-bit addresses cover all of it.
+    fn to_intel_hex(&self) -> String {
+        let mut text = String::new();
+        for (index, data) in self.to_binary().chunks(self.output.record_length).enumerate() {
+            intel_hex_record(&mut text, (index * self.output.record_length) as u16, 0x00, data);
+        }
+        intel_hex_record(&mut text, 0, 0x01, &[]);
+        text
+    }
+
+    /// Renders the binary image as S1 records between an S0 header and an S9
+    /// terminator. An S5 record holds the number of data records, or an S6
+    /// record if it doesn't fit in 16 bits.
+    fn to_srecord(&self) -> String {
+        let mut text = String::new();
+        srecord(&mut text, '0', &[0, 0], &[]);
+        let bytes = self.to_binary();
+        let records = bytes.chunks(self.output.record_length);
+        let count = records.len() as u32;
+        for (index, data) in records.enumerate() {
+            let address = (index * self.output.record_length) as u16;
+            srecord(&mut text, '1', &address.to_be_bytes(), data);
+        }
+        if count <= 0xffff {
+            srecord(&mut text, '5', &count.to_be_bytes()[2..], &[]);
+        } else {
+            srecord(&mut text, '6', &count.to_be_bytes()[1..], &[]);
+        }
+        srecord(&mut text, '9', &[0, 0], &[]);
+        text
+    }
+
+    fn to_readmemh(&self) -> String {
+        self.words.iter().map(|word| format!("{:04x}\n", word)).collect()
+    }
+
+    /// The words, or only the fill word for an empty program, for formats
+    /// that can't describe an empty memory.
+    fn nonempty_words(&self) -> Vec<u16> {
+        if self.words.is_empty() {
+            vec![self.output.fill]
+        } else {
+            self.words.clone()
+        }
+    }
+
+    /// Renders the words as the hex initialization vector of a `.coe` file.
+    fn to_coe(&self) -> String {
+        let vector: Vec<String> = self.nonempty_words().iter().map(|word| format!("{:04x}", word)).collect();
+        format!("memory_initialization_radix=16;\nmemory_initialization_vector=\n{};\n", vector.join(",\n"))
+    }
+
+    /// Renders the words as a `.mif` file with one word per address.
+    fn to_mif(&self) -> String {
+        let words = self.nonempty_words();
+        let mut text = format!("WIDTH=16;\nDEPTH={};\n\nADDRESS_RADIX=HEX;\nDATA_RADIX=HEX;\n\nCONTENT BEGIN\n", words.len());
+        for (address, word) in words.iter().enumerate() {
+            writeln!(text, "    {:04X} : {:04X};", address, word).unwrap();
+        }
+        text.push_str("END;\n");
+        text
+    }
+
+    /// Renders the words as a `hack_rom` package declaring `ROM`, an array
+    /// of `std_logic_vector(15 downto 0)` with one element per word.
+    fn to_vhdl(&self) -> String {
+        let mut text = String::from("library ieee;\nuse ieee.std_logic_1164.all;\n\npackage hack_rom is\n");
+        writeln!(text, "    constant ROM_DEPTH : natural := {};", self.words.len()).unwrap();
+        text.push_str("    type rom_type is array (0 to ROM_DEPTH - 1) of std_logic_vector(15 downto 0);\n");
+        text.push_str("    constant ROM : rom_type := (\n");
+        for (address, word) in self.words.iter().enumerate() {
+            writeln!(text, "        {} => x\"{:04X}\",", address, word).unwrap();
+        }
+        text.push_str("        others => (others => '0')\n    );\nend package hack_rom;\n");
+        text
+    }
+
+    /// Renders the words between `open` and `close`, one per line, followed
+    /// by `comment` and the instruction text when there is one.
+    fn to_array(&self, open: &str, close: &str, comment: &str) -> String {
+        let mut text = open.to_string();
+        for (address, word) in self.words.iter().enumerate() {
+            match self.comments.get(address) {
+                Some(instruction) if !instruction.is_empty() => {
+                    writeln!(text, "    {:#06x},{} {}", word, comment, instruction).unwrap()
+                }
+                _ => writeln!(text, "    {:#06x},", word).unwrap(),
+            }
+        }
+        text.push_str(close);
+        text
+    }
+
+    /// Renders the words as a `v2.0 raw` image, eight entries per line.
+    /// Runs of four or more equal words are written `count*word`.
+    fn to_logisim(&self) -> String {
+        let mut entries = Vec::new();
+        let mut words = self.words.iter().peekable();
+        while let Some(&word) = words.next() {
+            let mut count = 1;
+            while words.next_if_eq(&&word).is_some() {
+                count += 1;
+            }
+            if count >= 4 {
+                entries.push(format!("{}*{:x}", count, word));
+            } else {
+                entries.extend(std::iter::repeat_n(format!("{:x}", word), count));
+            }
+        }
+
+        let mut text = String::from("v2.0 raw\n");
+        for line in entries.chunks(8) {
+            text.push_str(&line.join(" "));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Appends `:LLAAAATT<data>CC`, the checksum being the two's complement of
+/// the sum of the other bytes.
+fn intel_hex_record(text: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+
+    text.push(':');
+    for byte in bytes {
+        write!(text, "{:02X}", byte).unwrap();
+    }
+    writeln!(text, "{:02X}", checksum).unwrap();
+}
+
+/// Appends `S<type><count><address><data><checksum>`, the checksum being the
+/// ones' complement of the sum of the count, address and data bytes.
+fn srecord(text: &mut String, kind: char, address: &[u8], data: &[u8]) {
+    let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+    bytes.extend(address);
+    bytes.extend(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    text.push('S');
+    text.push(kind);
+    for byte in bytes {
+        write!(text, "{:02X}", byte).unwrap();
+    }
+    writeln!(text, "{:02X}", checksum).unwrap();
+}
+
+/// Reads machine code in the Intel HEX format. Addresses not covered by a
+/// data record are zero.
+pub fn parse_intel_hex(text: &str, endian: Endian) -> Result<Vec<u16>, FormatError> {
+    let mut image = Image::default();
+    let mut base = 0;
+
+    for (line, record) in records(text) {
+        let Some(digits) = record.strip_prefix(':') else {
+            return Err(FormatError { line, message: format!("expected a record starting with `:`, found `{}`", record) });
+        };
+        let bytes = record_bytes(digits, line)?;
+        if bytes.len() < 5 || bytes[0] as usize != bytes.len() - 5 {
+            return Err(FormatError { line, message: "record length doesn't match its data".to_string() });
+        }
+        let (fields, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = fields.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+        check_sum(checksum[0], expected, line)?;
+
+        let address = u16::from_be_bytes([fields[1], fields[2]]) as usize;
+        let data = &fields[4..];
+        match fields[3] {
+            0x00 => image.write(base + address, data, line)?,
+            0x01 => return image.words(endian, line),
+            // extended segment and linear addresses
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+            // start addresses
+            0x03 | 0x05 => {}
+            kind => return Err(FormatError { line, message: format!("unsupported record type {:02X}", kind) }),
+        }
+    }
+
+    Err(FormatError { line: image.line, message: "missing end-of-file record".to_string() })
+}
+
+/// Reads machine code in the Motorola S-record format. Addresses not
+/// covered by a data record are zero. S5 and S6 record counts must match
+/// the number of data records before them.
+pub fn parse_srecord(text: &str, endian: Endian) -> Result<Vec<u16>, FormatError> {
+    let mut image = Image::default();
+    let mut data_records = 0;
+
+    for (line, record) in records(text) {
+        let mut chars = record.chars();
+        let (Some('S'), Some(kind)) = (chars.next(), chars.next()) else {
+            return Err(FormatError { line, message: format!("expected a record starting with `S`, found `{}`", record) });
+        };
+        let bytes = record_bytes(chars.as_str(), line)?;
+        if bytes.is_empty() || bytes[0] as usize != bytes.len() - 1 {
+            return Err(FormatError { line, message: "record length doesn't match its data".to_string() });
+        }
+        let (fields, checksum) = bytes.split_at(bytes.len() - 1);
+        check_sum(checksum[0], !fields.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)), line)?;
+
+        let address_size = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(FormatError { line, message: format!("unsupported record type S{}", kind) }),
+        };
+        if fields.len() < address_size + 1 {
+            return Err(FormatError { line, message: "record is too short for its address".to_string() });
+        }
+        let address = fields[1..=address_size].iter().fold(0, |address, byte| address << 8 | *byte as usize);
+        match kind {
+            '1' | '2' | '3' => {
+                image.write(address, &fields[address_size + 1..], line)?;
+                data_records += 1;
+            }
+            '5' | '6' if address != data_records => {
+                let message = format!("record count is {}, but there are {} data records", address, data_records);
+                return Err(FormatError { line, message });
+            }
+            '7' | '8' | '9' => return image.words(endian, line),
+            // header and matching record counts
+            _ => {}
+        }
+    }
+
+    Err(FormatError { line: image.line, message: "missing termination record".to_string() })
+}
+
+/// Numbered, trimmed, non-blank lines of a record file.
+fn records(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines().enumerate().map(|(number, line)| (number + 1, line.trim())).filter(|(_, line)| !line.is_empty())
+}
+
+fn record_bytes(digits: &str, line: usize) -> Result<Vec<u8>, FormatError> {
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(FormatError { line, message: format!("expected pairs of hex digits, found `{}`", digits) });
+    }
+    Ok((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect())
+}
+
+fn check_sum(found: u8, expected: u8, line: usize) -> Result<(), FormatError> {
+    if found != expected {
+        return Err(FormatError { line, message: format!("checksum is {:02X}, expected {:02X}", found, expected) });
+    }
+    Ok(())
+}
+
+/// ROM contents collected from data records.
+#[derive(Default)]
+struct Image {
+    bytes: Vec<u8>,
+    /// Line of the last record, for errors about the whole file.
+    line: usize,
+}
+
+impl Image {
+    fn write(&mut self, address: usize, data: &[u8], line: usize) -> Result<(), FormatError> {
+        self.line = line;
+        let end = address + data.len();
+        if end > ROM_SIZE * 2 {
+            return Err(FormatError { line, message: format!("address {:X} is outside the ROM", end - 1) });
+        }
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+        self.bytes[address..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn words(self, endian: Endian, line: usize) -> Result<Vec<u16>, FormatError> {
+        if !self.bytes.len().is_multiple_of(2) {
+            return Err(FormatError { line, message: "data ends in the middle of a word".to_string() });
+        }
+        let words = self.bytes.chunks(2).map(|pair| match endian {
+            Endian::Big => u16::from_be_bytes([pair[0], pair[1]]),
+            Endian::Little => u16::from_le_bytes([pair[0], pair[1]]),
+        });
+        Ok(words.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assembler(words: &[u16], output: OutputOptions) -> HackAssembler {
+        HackAssembler { output_file: String::new(), words: words.to_vec(), output, comments: Vec::new() }
+    }
+
+    /// Renders the words in a text format with default options, `comments`
+    /// being the instruction text of the first words.
+    fn render(format: OutputFormat, words: &[u16], comments: &[&str]) -> String {
+        let mut assembler = assembler(words, OutputOptions { format, ..OutputOptions::default() });
+        assembler.comments = comments.iter().map(|comment| comment.to_string()).collect();
+        String::from_utf8(assembler.render()).unwrap()
+    }
+
+    #[test]
+    fn test_binary() {
+        let options = OutputOptions { format: OutputFormat::Binary, ..OutputOptions::default() };
+        assert_eq!(assembler(&[0x0002, 0xec10], options.clone()).render(), [0x00, 0x02, 0xec, 0x10]);
+
+        let options = OutputOptions { endian: Endian::Little, ..options };
+        assert_eq!(assembler(&[0x0002, 0xec10], options).render(), [0x02, 0x00, 0x10, 0xec]);
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let options = OutputOptions { format: OutputFormat::IntelHex, record_length: 4, ..OutputOptions::default() };
+        let words = [0x0002, 0xec10, 0x0003, 0xe090, 0x0000];
+        let text = String::from_utf8(assembler(&words, options).render()).unwrap();
+        assert_eq!(text, ":040000000002EC10FE\n:040004000003E09085\n:020008000000F6\n:00000001FF\n");
+        assert_eq!(parse_intel_hex(&text, Endian::Big), Ok(words.to_vec()));
+        assert_eq!(parse_intel_hex(":020004000102F7\n:00000001FF", Endian::Little), Ok(vec![0, 0, 0x0201]));
+
+        let options = OutputOptions { format: OutputFormat::IntelHex, ..OutputOptions::default() };
+        let words = vec![7; ROM_SIZE];
+        let text = String::from_utf8(assembler(&words, options).render()).unwrap();
+        assert_eq!(text.lines().nth(4095), Some(":10FFF00000070007000700070007000700070007C9"));
+        assert_eq!(parse_intel_hex(&text, Endian::Big), Ok(words));
+    }
+
+    #[test]
+    fn test_intel_hex_errors() {
+        let error = |line, message: &str| Err(FormatError { line, message: message.to_string() });
+        assert_eq!(parse_intel_hex("\n:0400000000", Endian::Big), error(2, "record length doesn't match its data"));
+        assert_eq!(parse_intel_hex(":0200000000020B", Endian::Big), error(1, "checksum is 0B, expected FC"));
+        assert_eq!(parse_intel_hex("0200000000020B", Endian::Big), error(1, "expected a record starting with `:`, found `0200000000020B`"));
+        assert_eq!(parse_intel_hex(":0200000000GG00", Endian::Big), error(1, "expected pairs of hex digits, found `0200000000GG00`"));
+        assert_eq!(parse_intel_hex(":020000000002FC\n", Endian::Big), error(1, "missing end-of-file record"));
+        assert_eq!(parse_intel_hex(":02FFFF000102FD\n:00000001FF", Endian::Big), error(1, "address 10000 is outside the ROM"));
+        assert_eq!(parse_intel_hex(":0100000001FE\n:00000001FF", Endian::Big), error(2, "data ends in the middle of a word"));
+    }
+
+    #[test]
+    fn test_srecord() {
+        let options = OutputOptions { format: OutputFormat::SRecord, record_length: 4, ..OutputOptions::default() };
+        let words = [0x0002, 0xec10, 0x0003];
+        let text = String::from_utf8(assembler(&words, options).render()).unwrap();
+        assert_eq!(text, "S0030000FC\nS10700000002EC10FA\nS10500040003F3\nS5030002FA\nS9030000FC\n");
+        assert_eq!(parse_srecord(&text, Endian::Big), Ok(words.to_vec()));
+
+        let error = |line, message: &str| Err(FormatError { line, message: message.to_string() });
+        assert_eq!(parse_srecord("S10700000002EC10FB", Endian::Big), error(1, "checksum is FB, expected FA"));
+        assert_eq!(parse_srecord("S4030000FC", Endian::Big), error(1, "unsupported record type S4"));
+        assert_eq!(parse_srecord("S10200FD", Endian::Big), error(1, "record is too short for its address"));
+        assert_eq!(
+            parse_srecord("S10500000002F8\nS5030002FA\nS9030000FC", Endian::Big),
+            error(2, "record count is 2, but there are 1 data records")
+        );
+
+        // one record per byte of a full ROM needs a 24-bit count
+        let options = OutputOptions { format: OutputFormat::SRecord, record_length: 1, ..OutputOptions::default() };
+        let words = vec![0; ROM_SIZE];
+        let text = String::from_utf8(assembler(&words, options).render()).unwrap();
+        assert_eq!(text.lines().rev().nth(1), Some("S604010000FA"));
+        assert_eq!(parse_srecord(&text, Endian::Big), Ok(words));
+        assert_eq!(parse_srecord("S10700000002EC10FA", Endian::Big), error(1, "missing termination record"));
+    }
+
+    #[test]
+    fn test_memory_initialization() {
+        let words = [0x0002, 0xec10];
+        assert_eq!(render(OutputFormat::ReadMemH, &words, &[]), "0002\nec10\n");
+        assert_eq!(render(OutputFormat::ReadMemB, &words, &[]), "0000000000000010\n1110110000010000\n");
+        assert_eq!(render(OutputFormat::Coe, &words, &[]), "\
+memory_initialization_radix=16;
+memory_initialization_vector=
+0002,
+ec10;
+");
+        assert_eq!(render(OutputFormat::Mif, &words, &[]), "\
+WIDTH=16;
+DEPTH=2;
+
+ADDRESS_RADIX=HEX;
+DATA_RADIX=HEX;
+
+CONTENT BEGIN
+    0000 : 0002;
+    0001 : EC10;
+END;
+");
+        assert_eq!(render(OutputFormat::Coe, &[], &[]), "memory_initialization_radix=16;\nmemory_initialization_vector=\n0000;\n");
+        assert!(render(OutputFormat::Mif, &[], &[]).contains("DEPTH=1;\n"));
+        assert!(render(OutputFormat::Mif, &[], &[]).contains("CONTENT BEGIN\n    0000 : 0000;\nEND;\n"));
+        assert_eq!(render(OutputFormat::Vhdl, &words, &[]), "\
+library ieee;
+use ieee.std_logic_1164.all;
+
+package hack_rom is
+    constant ROM_DEPTH : natural := 2;
+    type rom_type is array (0 to ROM_DEPTH - 1) of std_logic_vector(15 downto 0);
+    constant ROM : rom_type := (
+        0 => x\"0002\",
+        1 => x\"EC10\",
+        others => (others => '0')
+    );
+end package hack_rom;
+");
+    }
+
+    #[test]
+    fn test_simulator_images() {
+        let words = [0x0002, 0xec10, 0, 0, 0, 0, 7, 7, 7, 1, 2, 3, 4, 5, 6];
+        assert_eq!(render(OutputFormat::Logisim, &words, &[]), "v2.0 raw\n2 ec10 4*0 7 7 7 1 2\n3 4 5 6\n");
+        assert_eq!(render(OutputFormat::Logisim, &[], &[]), "v2.0 raw\n");
+        assert_eq!(render(OutputFormat::Digital, &words[..4], &[]), "v2.0 raw\n0002\nec10\n0000\n0000\n");
+    }
+
+    #[test]
+    fn test_source_arrays() {
+        // the last word is padding, without an instruction
+        let words = [0x0002, 0xec10, 0x0000];
+        let comments = ["@2", "D=A"];
+
+        assert_eq!(render(OutputFormat::C, &words, &comments), "\
+#include <stdint.h>
+
+static const uint16_t prog[3] = {
+    0x0002, // @2
+    0xec10, // D=A
+    0x0000,
+};
+");
+        assert_eq!(render(OutputFormat::Rust, &words, &[]), "\
+pub static PROGRAM: [u16; 3] = [
+    0x0002,
+    0xec10,
+    0x0000,
+];
+");
+        assert_eq!(render(OutputFormat::Python, &words, &comments), "PROGRAM = [\n    0x0002,  # @2\n    0xec10,  # D=A\n    0x0000,\n]\n");
+    }
+
+    #[test]
+    fn test_pad() {
+        let options = OutputOptions { rom_size: Some(4), fill: 0xffff, ..OutputOptions::default() };
+        let mut words = vec![1, 2];
+        assert_eq!(options.pad(&mut words), Ok(()));
+        assert_eq!(words, [1, 2, 0xffff, 0xffff]);
+
+        let mut words = vec![0; 5];
+        assert_eq!(options.pad(&mut words), Err("program has 5 words, but the ROM size is 4".to_string()));
+        assert!(OutputOptions::default().pad(&mut words).is_ok());
+        assert_eq!(words.len(), 5);
+    }
+}
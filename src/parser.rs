@@ -0,0 +1,417 @@
+use std::fmt;
+
+use crate::error::{AssemblyError, Span};
+use crate::expression::Expr;
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::macros;
+
+/// Part of a C-instruction (dest, comp or jump) as written in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub text: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    /// Decimal literal, possibly negative so it can be reported as out of range.
+    Constant(String),
+    Symbol(String),
+    /// Anything else, like `SCREEN+32` or `0x4000`. Expressions don't
+    /// allocate variables, they can only use those of earlier lines.
+    Expression { expr: Expr, text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /// `@value`, the span points at the operand.
+    AInstr { operand: Operand, span: Span },
+    /// `dest=comp;jump`, the span points at the start of the instruction.
+    CInstr { dest: Option<Field>, comp: Field, jump: Option<Field>, span: Span },
+    /// `(name)`, the span points at the name.
+    Label { name: String, span: Span },
+    /// `.equ name value`, `.define name value` or `(name = value)`, the
+    /// span points at the name.
+    Constant { name: String, value: Expr, text: String, span: Span },
+}
+
+impl Statement {
+    pub fn span(&self) -> &Span {
+        match self {
+            Statement::AInstr { span, .. }
+            | Statement::CInstr { span, .. }
+            | Statement::Label { span, .. }
+            | Statement::Constant { span, .. } => span,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Constant(text) | Operand::Symbol(text) | Operand::Expression { text, .. } => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Writes the statement with macro arguments substituted. C-instructions
+/// lose their whitespace, expressions are kept as written.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::AInstr { operand, .. } => write!(f, "@{}", operand),
+            Statement::CInstr { dest, comp, jump, .. } => {
+                if let Some(dest) = dest {
+                    write!(f, "{}=", dest.text)?;
+                }
+                write!(f, "{}", comp.text)?;
+                if let Some(jump) = jump {
+                    write!(f, ";{}", jump.text)?;
+                }
+                Ok(())
+            }
+            Statement::Label { name, .. } => write!(f, "({})", name),
+            Statement::Constant { name, text, .. } => write!(f, "({} = {})", name, text),
+        }
+    }
+}
+
+/// Parses the source into statements, one per non-empty line after macro
+/// expansion. Lines that can't be parsed are reported as errors and left out.
+pub fn parse(source: &str, file: &str) -> (Vec<Statement>, Vec<AssemblyError>) {
+    parse_lines(split_lines(tokenize(source, file)))
+}
+
+/// Splits the tokens into lines, each ending with its `Newline` token.
+pub(crate) fn split_lines(tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    tokens.split_inclusive(|token| token.kind == TokenKind::Newline).map(<[Token]>::to_vec).collect()
+}
+
+/// Like [`parse`], for lines that are already split into tokens.
+pub(crate) fn parse_lines(lines: Vec<Vec<Token>>) -> (Vec<Statement>, Vec<AssemblyError>) {
+    let (lines, mut errors) = macros::expand(lines);
+    let mut statements = Vec::new();
+
+    for line in &lines {
+        let (newline, line) = line.split_last().unwrap();
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_statement(line, newline) {
+            Ok(statement) => statements.push(statement),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    (statements, errors)
+}
+
+fn parse_statement(line: &[Token], newline: &Token) -> Result<Statement, AssemblyError> {
+    match &line[0].kind {
+        TokenKind::At => parse_a_instruction(&line[1..], newline),
+        TokenKind::LParen if line.get(2).is_some_and(|token| token.kind == TokenKind::Equals) => {
+            let value = match line.last() {
+                Some(Token { kind: TokenKind::RParen, .. }) => &line[3..line.len() - 1],
+                _ => &[],
+            };
+            parse_constant(line, line.get(1), value)
+        }
+        TokenKind::LParen => parse_label(line),
+        TokenKind::Identifier(directive) if directive == ".equ" || directive == ".define" => {
+            parse_constant(line, line.get(1), line.get(2..).unwrap_or_default())
+        }
+        _ => Ok(parse_c_instruction(line, newline)),
+    }
+}
+
+fn parse_a_instruction(operand: &[Token], newline: &Token) -> Result<Statement, AssemblyError> {
+    use TokenKind::*;
+
+    let span = span_or(operand, newline);
+    let kinds: Vec<&TokenKind> = operand.iter().map(|token| &token.kind).collect();
+    let operand = match kinds[..] {
+        [Identifier(name)] => Operand::Symbol(name.clone()),
+        [Integer(digits)] if is_decimal(digits) => Operand::Constant(digits.clone()),
+        [Operator('-'), Integer(digits)] if is_decimal(digits) => Operand::Constant(format!("-{}", digits)),
+        _ => match Expr::parse(operand) {
+            Some(expr) => Operand::Expression { expr, text: source_text(operand) },
+            None => return Err(AssemblyError::BadAInstruction { operand: source_text(operand), span }),
+        },
+    };
+
+    Ok(Statement::AInstr { operand, span })
+}
+
+fn is_decimal(digits: &str) -> bool {
+    digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_label(line: &[Token]) -> Result<Statement, AssemblyError> {
+    match line {
+        [_, Token { kind: TokenKind::Identifier(name), span }, Token { kind: TokenKind::RParen, .. }] => {
+            Ok(Statement::Label { name: name.clone(), span: span.clone() })
+        }
+        _ => Err(AssemblyError::MalformedLabel { label: source_text(line), span: line[0].span.clone() }),
+    }
+}
+
+fn parse_constant(line: &[Token], name: Option<&Token>, value: &[Token]) -> Result<Statement, AssemblyError> {
+    match (name, Expr::parse(value)) {
+        (Some(Token { kind: TokenKind::Identifier(name), span }), Some(expr)) => Ok(Statement::Constant {
+            name: name.clone(),
+            value: expr,
+            text: source_text(value),
+            span: span.clone(),
+        }),
+        _ => Err(AssemblyError::MalformedConstant { definition: source_text(line), span: line[0].span.clone() }),
+    }
+}
+
+fn parse_c_instruction(line: &[Token], newline: &Token) -> Statement {
+    let position = |kind: TokenKind| line.iter().position(|token| token.kind == kind);
+
+    let jump_start = position(TokenKind::Semicolon);
+    let comp_end = jump_start.unwrap_or(line.len());
+    let comp_start = match position(TokenKind::Equals) {
+        Some(pos) if pos < comp_end => Some(pos),
+        _ => None,
+    };
+
+    // empty fields point at the token that follows them
+    let field = |tokens: &[Token], next: Option<&Token>| Field {
+        text: field_text(tokens),
+        span: span_or(tokens, next.unwrap_or(newline)),
+    };
+
+    let dest = comp_start.map(|pos| field(&line[..pos], line.get(pos)));
+    let comp_tokens = &line[comp_start.map_or(0, |pos| pos + 1)..comp_end];
+    let comp = field(comp_tokens, line.get(comp_end));
+    let jump = jump_start.map(|pos| field(&line[pos + 1..], None));
+
+    Statement::CInstr { dest, comp, jump, span: line[0].span.clone() }
+}
+
+/// Span of the first token, or of `next` when there are no tokens.
+fn span_or(tokens: &[Token], next: &Token) -> Span {
+    tokens.first().unwrap_or(next).span.clone()
+}
+
+/// Text of the tokens with the whitespace between them removed, so that
+/// `D + 1` reads `D+1`. Whitespace between two names or numbers is kept as
+/// a single space, `A M` is not `AM`.
+fn field_text(tokens: &[Token]) -> String {
+    let is_word = |kind: &TokenKind| matches!(kind, TokenKind::Identifier(_) | TokenKind::Integer(_));
+
+    let mut text = String::new();
+    for pair in tokens.windows(2) {
+        text += &pair[0].kind.to_string();
+        if is_word(&pair[0].kind) && is_word(&pair[1].kind) && separated(&pair[0], &pair[1]) {
+            text.push(' ');
+        }
+    }
+    if let Some(last) = tokens.last() {
+        text += &last.kind.to_string();
+    }
+
+    text
+}
+
+/// True if the line starts with the directive, like `.macro`.
+pub(crate) fn is_directive(line: &[Token], directive: &str) -> bool {
+    matches!(&line[0].kind, TokenKind::Identifier(name) if name == directive)
+}
+
+/// True if the source has whitespace between the two tokens. Tokens from
+/// different places, like a macro body and the arguments of its call, are
+/// never separated.
+pub(crate) fn separated(previous: &Token, token: &Token) -> bool {
+    let end = previous.span.column + previous.kind.to_string().chars().count();
+    previous.span.line == token.span.line && previous.span.expansion == token.span.expansion && end < token.span.column
+}
+
+/// Text of the tokens, with a single space wherever the source had whitespace.
+pub(crate) fn source_text(tokens: &[Token]) -> String {
+    let mut text = String::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 && separated(&tokens[index - 1], token) {
+            text.push(' ');
+        }
+        text += &token.kind.to_string();
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statements(source: &str) -> Vec<Statement> {
+        let (statements, errors) = parse(source, "test.asm");
+        assert_eq!(errors, vec![]);
+        statements
+    }
+
+    fn position(span: &Span) -> (usize, usize) {
+        (span.line, span.column)
+    }
+
+    #[test]
+    fn test_parse_skips_comments() {
+        let statements = statements("\
+// comment
+
+@2
+  @3  // in-line comment");
+
+        assert_eq!(statements.len(), 2);
+        assert_eq!(position(statements[0].span()), (3, 2));
+        assert_eq!(position(statements[1].span()), (4, 4));
+    }
+
+    #[test]
+    fn test_statement_types() {
+        let statements = statements("\
+@2
+@sum
+D=0
+(END)");
+
+        match &statements[..] {
+            [Statement::AInstr { operand: Operand::Constant(value), .. }, Statement::AInstr { operand: Operand::Symbol(symbol), .. }, Statement::CInstr { .. }, Statement::Label { name, .. }] =>
+            {
+                assert_eq!((&value[..], &symbol[..], &name[..]), ("2", "sum", "END"));
+            }
+            statements => panic!("Unexpected statements: {:?}", statements),
+        }
+    }
+
+    #[test]
+    fn test_dest_comp_jump() {
+        let statements = statements("\
+D=0
+D=D+1;JLE
+  D;JGT
+AM=M-1");
+
+        let fields: Vec<_> = statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::CInstr { dest, comp, jump, .. } => (
+                    dest.as_ref().map(|d| (d.text.as_str(), position(&d.span))),
+                    (comp.text.as_str(), position(&comp.span)),
+                    jump.as_ref().map(|j| (j.text.as_str(), position(&j.span))),
+                ),
+                statement => panic!("Expected Statement::CInstr, got {:?}", statement),
+            })
+            .collect();
+
+        assert_eq!(fields, vec![
+            (Some(("D", (1, 1))), ("0", (1, 3)), None),
+            (Some(("D", (2, 1))), ("D+1", (2, 3)), Some(("JLE", (2, 7)))),
+            (None, ("D", (3, 3)), Some(("JGT", (3, 5)))),
+            (Some(("AM", (4, 1))), ("M-1", (4, 4)), None),
+        ]);
+    }
+
+    #[test]
+    fn test_whitespace_inside_instructions() {
+        let statements = statements("D = D + A ; JGT\n\tAM\t=\t- 1\n@ i\n( LOOP )\nA M=D\n");
+
+        match &statements[..] {
+            [Statement::CInstr { dest: Some(dest), comp, jump: Some(jump), .. }, Statement::CInstr { dest: Some(dest2), comp: comp2, .. }, Statement::AInstr { operand: Operand::Symbol(symbol), span }, Statement::Label { name, .. }, Statement::CInstr { dest: Some(spaced), .. }] =>
+            {
+                assert_eq!((&dest.text[..], &comp.text[..], &jump.text[..]), ("D", "D+A", "JGT"));
+                assert_eq!((position(&comp.span), position(&jump.span)), ((1, 5), (1, 13)));
+                assert_eq!((&dest2.text[..], &comp2.text[..]), ("AM", "-1"));
+                assert_eq!((&symbol[..], position(span)), ("i", (3, 3)));
+                assert_eq!(name, "LOOP");
+                assert_eq!(spaced.text, "A M");
+            }
+            statements => panic!("Unexpected statements: {:?}", statements),
+        }
+    }
+
+    #[test]
+    fn test_empty_fields() {
+        let statements = statements("=;\nD=");
+
+        match &statements[..] {
+            [Statement::CInstr { dest: Some(dest), comp, jump: Some(jump), .. }, Statement::CInstr { comp: empty, .. }] => {
+                assert_eq!((&dest.text[..], position(&dest.span)), ("", (1, 1)));
+                assert_eq!((&comp.text[..], position(&comp.span)), ("", (1, 2)));
+                assert_eq!((&jump.text[..], position(&jump.span)), ("", (1, 3)));
+                assert_eq!((&empty.text[..], position(&empty.span)), ("", (2, 3)));
+            }
+            statements => panic!("Unexpected statements: {:?}", statements),
+        }
+    }
+
+    #[test]
+    fn test_negative_constant() {
+        match &statements("@-1")[..] {
+            [Statement::AInstr { operand: Operand::Constant(value), span }] => {
+                assert_eq!((&value[..], position(span)), ("-1", (1, 2)));
+            }
+            statements => panic!("Unexpected statements: {:?}", statements),
+        }
+    }
+
+    #[test]
+    fn test_expression_operand() {
+        match &statements("@ SCREEN + 0x20\n@0b11")[..] {
+            [Statement::AInstr { operand: Operand::Expression { text, .. }, span }, Statement::AInstr { operand: Operand::Expression { expr, .. }, .. }] =>
+            {
+                assert_eq!((&text[..], position(span)), ("SCREEN + 0x20", (1, 3)));
+                assert_eq!(*expr, Expr::Number(3));
+            }
+            statements => panic!("Unexpected statements: {:?}", statements),
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let statements = statements(".equ MAX_SIZE 512\n.define ROW 32 * 2\n( BASE = SCREEN + ROW )\n");
+        let constants: Vec<_> = statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Constant { name, text, span, .. } => (&name[..], &text[..], position(span)),
+                statement => panic!("Expected Statement::Constant, got {:?}", statement),
+            })
+            .collect();
+
+        assert_eq!(constants, vec![
+            ("MAX_SIZE", "512", (1, 6)),
+            ("ROW", "32 * 2", (2, 9)),
+            ("BASE", "SCREEN + ROW", (3, 3)),
+        ]);
+
+        let (statements, errors) = parse(".equ X\n.equ 1 2\n(X = 1\n(X =)\n.define\n", "test.asm");
+        assert!(statements.is_empty());
+        let errors: Vec<_> = errors.iter().map(|err| (err.to_string(), position(err.span()))).collect();
+        assert_eq!(errors, vec![
+            ("malformed constant definition `.equ X`".to_string(), (1, 1)),
+            ("malformed constant definition `.equ 1 2`".to_string(), (2, 1)),
+            ("malformed constant definition `(X = 1`".to_string(), (3, 1)),
+            ("malformed constant definition `(X =)`".to_string(), (4, 1)),
+            ("malformed constant definition `.define`".to_string(), (5, 1)),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (statements, errors) = parse("(A\n@2\n@1x\n(L) D\n@\n(1ABC)", "test.asm");
+
+        assert_eq!(statements.len(), 1);
+        let errors: Vec<_> = errors.iter().map(|err| (err.to_string(), position(err.span()))).collect();
+        assert_eq!(errors, vec![
+            ("malformed label `(A`".to_string(), (1, 1)),
+            ("bad A-instruction operand `1x`".to_string(), (3, 2)),
+            ("malformed label `(L) D`".to_string(), (4, 1)),
+            ("bad A-instruction operand ``".to_string(), (5, 2)),
+            ("malformed label `(1ABC)`".to_string(), (6, 1)),
+        ]);
+    }
+}
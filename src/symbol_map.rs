@@ -0,0 +1,111 @@
+use std::fmt::Write;
+
+use crate::debug_info::json_string;
+use crate::symbol_table::{SymbolKind, SymbolTable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymbolMapFormat {
+    /// Aligned columns for people to read.
+    #[default]
+    Text,
+    /// A JSON object with a `symbols` array, for tools.
+    Json,
+}
+
+pub(crate) struct Entry<'a> {
+    pub name: &'a str,
+    pub kind: SymbolKind,
+    pub address: i32,
+    /// File and line of the definition, `None` for predefined symbols.
+    pub location: Option<(&'a str, usize)>,
+}
+
+/// Symbols sorted by kind, then address, then name.
+pub(crate) fn entries(symbols: &SymbolTable) -> Vec<Entry<'_>> {
+    let mut entries: Vec<_> = symbols
+        .iter()
+        .map(|(name, address)| Entry {
+            name,
+            kind: symbols.kind(name).unwrap(),
+            address,
+            location: symbols.definition(name).map(|span| (&*span.call_site().file, span.call_site().line)),
+        })
+        .collect();
+    entries.sort_by_key(|entry| (entry.kind, entry.address, entry.name));
+    entries
+}
+
+/// Renders every symbol with its kind, address and the file and line
+/// defining it, i.e. the label definition or the first use of a variable.
+/// Symbols from macro bodies are attributed to the line calling the macro.
+pub fn symbol_map(symbols: &SymbolTable, format: SymbolMapFormat) -> String {
+    let entries = entries(symbols);
+    let mut text = String::new();
+
+    match format {
+        SymbolMapFormat::Text => {
+            writeln!(text, "{:<24} {:<10} {:>5}  {:>5}  file", "name", "kind", "addr", "line").unwrap();
+            for entry in entries {
+                let (file, line) = entry.location.map_or(("", "-".to_string()), |(file, line)| (file, line.to_string()));
+                let row = format!("{:<24} {:<10} {:>5}  {:>5}  {}", entry.name, entry.kind, entry.address, line, file);
+                writeln!(text, "{}", row.trim_end()).unwrap();
+            }
+        }
+        SymbolMapFormat::Json => {
+            text.push_str("{\n  \"symbols\": [");
+            for (index, entry) in entries.iter().enumerate() {
+                let (file, line) = match entry.location {
+                    Some((file, line)) => (json_string(file), line.to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                // symbols only contain letters, digits and `_.$:`, nothing to escape
+                write!(
+                    text,
+                    "{}\n    {{\"name\": \"{}\", \"kind\": \"{}\", \"address\": {}, \"file\": {}, \"line\": {}}}",
+                    if index == 0 { "" } else { "," },
+                    entry.name,
+                    entry.kind,
+                    entry.address,
+                    file,
+                    line
+                )
+                .unwrap();
+            }
+            text.push_str("\n  ]\n}\n");
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble;
+
+    const SOURCE: &str = "@i\nM=0\n(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n";
+
+    #[test]
+    fn test_symbol_map_text() {
+        let text = symbol_map(&assemble(SOURCE).unwrap().symbols, SymbolMapFormat::Text);
+        let lines: Vec<_> = text.lines().collect();
+
+        assert_eq!(lines.len(), 26);
+        assert_eq!(lines[0], "name                     kind        addr   line  file");
+        assert_eq!(lines[1], "LOOP                     label          2      3  <source>");
+        assert_eq!(lines[2], "i                        variable      16      1  <source>");
+        assert_eq!(lines[3], "R0                       predefined     0      -");
+        assert_eq!(lines[25], "KBD                      predefined 24576      -");
+    }
+
+    #[test]
+    fn test_symbol_map_json() {
+        let json = symbol_map(&assemble(SOURCE).unwrap().symbols, SymbolMapFormat::Json);
+
+        assert!(json.starts_with("{\n  \"symbols\": [\n    \
+            {\"name\": \"LOOP\", \"kind\": \"label\", \"address\": 2, \"file\": \"<source>\", \"line\": 3},\n    \
+            {\"name\": \"i\", \"kind\": \"variable\", \"address\": 16, \"file\": \"<source>\", \"line\": 1},\n    \
+            {\"name\": \"R0\", \"kind\": \"predefined\", \"address\": 0, \"file\": null, \"line\": null},\n"));
+        assert!(json.ends_with("{\"name\": \"KBD\", \"kind\": \"predefined\", \"address\": 24576, \"file\": null, \"line\": null}\n  ]\n}\n"));
+    }
+}
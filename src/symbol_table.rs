@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::Span;
+use crate::memory::{KBD, SCREEN};
+
+/// Symbols every program can use without defining them.
+const PREDEFINED: [(&str, i32); 23] = [
+    ("R0", 0),
+    ("R1", 1),
+    ("R2", 2),
+    ("R3", 3),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", SCREEN as i32),
+    ("KBD", KBD as i32),
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+];
+
+/// What a symbol names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SymbolKind {
+    /// ROM address of a `(LABEL)`.
+    Label,
+    /// RAM address allocated for a variable.
+    Variable,
+    /// Value of a `.equ` or `(NAME = value)` definition.
+    Constant,
+    /// R0..R15, SCREEN, KBD and the VM pointers.
+    Predefined,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            SymbolKind::Label => "label",
+            SymbolKind::Variable => "variable",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Predefined => "predefined",
+        })
+    }
+}
+
+/// Names resolved to RAM or ROM addresses.
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    symbols: HashMap<String, i32>,
+    /// Where labels and variables were defined, predefined symbols have no entry.
+    definitions: HashMap<String, (SymbolKind, Span)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: PREDEFINED.iter().map(|&(name, address)| (name.to_string(), address)).collect(),
+            definitions: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.symbols.contains_key(symbol)
+    }
+
+    pub fn add_entry(&mut self, symbol: String, address: i32, kind: SymbolKind, span: Span) {
+        self.definitions.insert(symbol.clone(), (kind, span));
+        self.symbols.insert(symbol, address);
+    }
+
+    pub fn get_address(&self, symbol: &str) -> Option<&i32> {
+        self.symbols.get(symbol)
+    }
+
+    /// Location of the label definition or the first use of the variable.
+    pub fn definition(&self, symbol: &str) -> Option<&Span> {
+        self.definitions.get(symbol).map(|(_, span)| span)
+    }
+
+    pub fn kind(&self, symbol: &str) -> Option<SymbolKind> {
+        match self.definitions.get(symbol) {
+            Some(&(kind, _)) => Some(kind),
+            None if self.contains(symbol) => Some(SymbolKind::Predefined),
+            None => None,
+        }
+    }
+
+    /// Iterates over all symbols in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i32)> {
+        self.symbols.iter().map(|(name, &address)| (name.as_str(), address))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_symboltable() {
+        let mut symbols = SymbolTable::new();
+        let span = Span::new(Rc::from("test.asm"), 4, 2);
+
+        assert!(!symbols.contains("END"));
+        symbols.add_entry("END".to_string(), 123, SymbolKind::Label, span.clone());
+        assert!(symbols.contains("END"));
+        assert_eq!(symbols.get_address("END"), Some(&123));
+        assert_eq!(symbols.definition("END"), Some(&span));
+        assert_eq!(symbols.definition("KBD"), None);
+        assert_eq!(symbols.kind("END"), Some(SymbolKind::Label));
+        assert_eq!(symbols.kind("KBD"), Some(SymbolKind::Predefined));
+        assert_eq!(symbols.kind("START"), None);
+        assert_eq!(symbols.get_address("START"), None);
+    }
+
+    #[test]
+    fn test_predefined_symbols() {
+        let symbols = SymbolTable::new();
+
+        assert_eq!(symbols.get_address("R2"), Some(&2));
+        assert_eq!(symbols.get_address("R15"), Some(&15));
+        assert_eq!(symbols.get_address("SCREEN"), Some(&16384));
+        assert_eq!(symbols.get_address("KBD"), Some(&24576));
+        assert_eq!(symbols.get_address("SP"), Some(&0));
+        assert_eq!(symbols.get_address("LCL"), Some(&1));
+        assert_eq!(symbols.get_address("ARG"), Some(&2));
+        assert_eq!(symbols.get_address("THIS"), Some(&3));
+        assert_eq!(symbols.get_address("THAT"), Some(&4));
+        assert_eq!(symbols.get_address("r2"), None);
+    }
+}
\ No newline at end of file
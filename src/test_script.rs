@@ -0,0 +1,560 @@
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::emulator::Emulator;
+use crate::memory::RAM_SIZE;
+use crate::{assemble_source, is_machine_code, parse_machine_code, AssemblyErrors, Endian, Options};
+
+/// Malformed command in a test script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ScriptError {}
+
+/// Register or memory cell a script can set or output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Ram(usize),
+    A,
+    D,
+    PC,
+    /// Number of instructions executed so far, output only.
+    Time,
+}
+
+/// Output column such as `RAM[0]%D2.6.2`, the value is written in `format`
+/// with `width` characters and surrounded by the given padding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub target: Target,
+    pub format: char,
+    pub pad_left: usize,
+    pub width: usize,
+    pub pad_right: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Target, u16),
+    Repeat(usize, Vec<Command>),
+    TickTock,
+    Output,
+    /// `echo` and `clear-echo` only talk to the GUI, so they do nothing.
+    Echo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    /// `,` or `;`, the simulator treats both as the end of a command.
+    Separator,
+    Open,
+    Close,
+}
+
+fn tokenize(script: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut chars = script.chars().peekable();
+    let mut line = 1;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            _ if c.is_whitespace() => (),
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            ',' | ';' => tokens.push((line, Token::Separator)),
+            '{' => tokens.push((line, Token::Open)),
+            '}' => tokens.push((line, Token::Close)),
+            '"' => {
+                let text: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                tokens.push((line, Token::Word(text)));
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !",;{}".contains(c)) {
+                    word.push(c);
+                }
+                tokens.push((line, Token::Word(word)));
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Parses a CPUEmulator test script.
+pub fn parse_script(script: &str) -> Result<Vec<Command>, ScriptError> {
+    let tokens = tokenize(script);
+    let mut position = 0;
+    let commands = parse_commands(&tokens, &mut position)?;
+
+    match tokens.get(position) {
+        Some((line, _)) => Err(ScriptError { line: *line, message: "unexpected `}`".to_string() }),
+        None => Ok(commands),
+    }
+}
+
+/// Parses commands up to the end of the script or a closing brace.
+fn parse_commands(tokens: &[(usize, Token)], position: &mut usize) -> Result<Vec<Command>, ScriptError> {
+    let mut commands = Vec::new();
+
+    while let Some((line, token)) = tokens.get(*position) {
+        let line = *line;
+        let error = |message: String| ScriptError { line, message };
+
+        let words: Vec<&str> = tokens[*position..]
+            .iter()
+            .map_while(|(_, token)| match token {
+                Token::Word(word) => Some(word.as_str()),
+                _ => None,
+            })
+            .collect();
+        *position += words.len();
+
+        let command = match words[..] {
+            [] if *token == Token::Close => break,
+            [] if *token == Token::Separator => {
+                *position += 1;
+                continue;
+            }
+            ["repeat", count] => {
+                let count = count.parse::<usize>().map_err(|_| error(format!("bad repeat count `{}`", count)))?;
+                if tokens.get(*position).map(|(_, token)| token) != Some(&Token::Open) {
+                    return Err(error("expected `{` after repeat".to_string()));
+                }
+                *position += 1;
+                let body = parse_commands(tokens, position)?;
+                if tokens.get(*position).map(|(_, token)| token) != Some(&Token::Close) {
+                    return Err(error("missing `}` after repeat".to_string()));
+                }
+                *position += 1;
+                Command::Repeat(count, body)
+            }
+            ["load", file] => Command::Load(file.to_string()),
+            ["output-file", file] => Command::OutputFile(file.to_string()),
+            ["compare-to", file] => Command::CompareTo(file.to_string()),
+            ["output-list", ref columns @ ..] => Command::OutputList(
+                columns
+                    .iter()
+                    .map(|column| parse_column(column).ok_or_else(|| error(format!("bad output column `{}`", column))))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ["set", target, value] => {
+                let target = match parse_target(target) {
+                    Some(Target::Time) | None => return Err(error(format!("can't set `{}`", target))),
+                    Some(target) => target,
+                };
+                let value = parse_value(value).ok_or_else(|| error(format!("bad value `{}`", value)))?;
+                Command::Set(target, value)
+            }
+            ["ticktock"] => Command::TickTock,
+            ["output"] => Command::Output,
+            ["echo", ..] | ["clear-echo"] => Command::Echo,
+            [] => return Err(error("expected a command".to_string())),
+            _ => return Err(error(format!("unsupported command `{}`", words.join(" ")))),
+        };
+        // a repeat block needs no separator after its closing brace
+        let is_block = matches!(command, Command::Repeat(..));
+        commands.push(command);
+
+        match tokens.get(*position) {
+            Some((_, Token::Separator)) => *position += 1,
+            Some((_, Token::Close)) | None => (),
+            Some(_) if is_block => (),
+            Some((line, _)) => return Err(ScriptError { line: *line, message: "expected `,` or `;`".to_string() }),
+        }
+    }
+
+    Ok(commands)
+}
+
+fn parse_target(name: &str) -> Option<Target> {
+    match name {
+        "A" => Some(Target::A),
+        "D" => Some(Target::D),
+        "PC" => Some(Target::PC),
+        "time" => Some(Target::Time),
+        _ => {
+            let address = name.strip_prefix("RAM[")?.strip_suffix(']')?.parse::<usize>().ok()?;
+            (address < RAM_SIZE).then_some(Target::Ram(address))
+        }
+    }
+}
+
+/// Parses `-1`, `%D-1`, `%X1F` or `%B101`.
+fn parse_value(value: &str) -> Option<u16> {
+    let (radix, digits) = match value.strip_prefix('%') {
+        Some(rest) => match rest.split_at_checked(1)? {
+            ("D", digits) => (10, digits),
+            ("X", digits) => (16, digits),
+            ("B", digits) => (2, digits),
+            _ => return None,
+        },
+        None => (10, value),
+    };
+
+    match radix {
+        10 => digits.parse::<i16>().map(|value| value as u16).ok(),
+        _ => u16::from_str_radix(digits, radix).ok(),
+    }
+}
+
+/// Parses `name%F<left>.<width>.<right>`, columns without a format are
+/// written like `%D1.6.1`.
+fn parse_column(column: &str) -> Option<Column> {
+    let (name, format) = column.split_once('%').unwrap_or((column, "D1.6.1"));
+    let target = parse_target(name)?;
+    let (format, sizes) = format.split_at_checked(1)?;
+    let format = format.chars().next()?;
+    if !"DXBS".contains(format) {
+        return None;
+    }
+
+    let sizes = sizes.split('.').map(|size| size.parse::<usize>().ok()).collect::<Option<Vec<_>>>()?;
+    match sizes[..] {
+        [pad_left, width, pad_right] => {
+            Some(Column { name: name.to_string(), target, format, pad_left, width, pad_right })
+        }
+        _ => None,
+    }
+}
+
+/// Output line that differs from the compare file, `line` counts the
+/// header as line 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TestReport {
+    /// Everything written by `output-list` and `output`.
+    pub output: String,
+    pub output_file: Option<String>,
+    pub compare_file: Option<String>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+struct TestRun {
+    emulator: Emulator,
+    time: usize,
+    columns: Vec<Column>,
+    report: TestReport,
+}
+
+impl TestRun {
+    fn value(&self, target: Target) -> u16 {
+        match target {
+            Target::Ram(address) => self.emulator.ram[address],
+            Target::A => self.emulator.a,
+            Target::D => self.emulator.d,
+            Target::PC => self.emulator.pc,
+            Target::Time => self.time as u16,
+        }
+    }
+
+    fn execute(&mut self, commands: &[Command], dir: &Path) -> Result<(), Box<dyn Error>> {
+        for command in commands {
+            match command {
+                Command::Load(file) => {
+                    self.emulator = Emulator::new(&load_program(&dir.join(file))?);
+                    self.time = 0;
+                }
+                Command::OutputFile(file) => self.report.output_file = Some(file.clone()),
+                Command::CompareTo(file) => self.report.compare_file = Some(file.clone()),
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let header = format_line(&self.columns, |column| {
+                        let width = column.pad_left + column.width + column.pad_right;
+                        let name: String = column.name.chars().take(width).collect();
+                        let left = (width - name.len()) / 2;
+                        format!("{}{}{}", " ".repeat(left), name, " ".repeat(width - left - name.len()))
+                    });
+                    self.report.output += &header;
+                }
+                Command::Set(target, value) => match target {
+                    Target::Ram(address) => self.emulator.ram[*address] = *value,
+                    Target::A => self.emulator.a = *value,
+                    Target::D => self.emulator.d = *value,
+                    Target::PC => self.emulator.pc = *value,
+                    Target::Time => unreachable!("rejected by the parser"),
+                },
+                Command::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.execute(body, dir)?;
+                    }
+                }
+                Command::TickTock => {
+                    self.emulator.step();
+                    self.time += 1;
+                }
+                Command::Output => {
+                    let line = format_line(&self.columns, |column| {
+                        let value = format_value(self.value(column.target), column);
+                        format!("{}{}{}", " ".repeat(column.pad_left), value, " ".repeat(column.pad_right))
+                    });
+                    self.report.output += &line;
+                }
+                Command::Echo => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn format_line(columns: &[Column], cell: impl Fn(&Column) -> String) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        write!(line, "{}|", cell(column)).unwrap();
+    }
+    line.push('\n');
+    line
+}
+
+fn format_value(value: u16, column: &Column) -> String {
+    let width = column.width;
+    let text = match column.format {
+        'B' => format!("{:016b}", value),
+        'X' => format!("{:04X}", value),
+        _ => (value as i16).to_string(),
+    };
+
+    match column.format {
+        // numbers in binary and hex keep their lowest digits
+        'B' | 'X' if text.len() > width => text[text.len() - width..].to_string(),
+        'B' | 'X' => format!("{:0>width$}", text),
+        'S' => format!("{:<width$}", text),
+        _ => format!("{:>width$}", text),
+    }
+}
+
+/// Reads a machine code file, or a source file after assembling it.
+fn load_program(path: &Path) -> Result<Vec<u16>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    if is_machine_code(path) {
+        return Ok(parse_machine_code(&contents, Endian::default())?);
+    }
+
+    let file = path.to_string_lossy();
+    match assemble_source(&contents, &file, &Options { includes: true, ..Options::default() }) {
+        Ok(program) => Ok(program.words),
+        Err(diagnostics) => Err(Box::new(AssemblyErrors::new(diagnostics, None))),
+    }
+}
+
+/// Runs the script with files resolved relative to `dir`, writes the output
+/// file and compares the output with the compare file if the script names them.
+pub fn run_script(commands: &[Command], dir: &Path) -> Result<TestReport, Box<dyn Error>> {
+    let mut run = TestRun {
+        emulator: Emulator::new(&[]),
+        time: 0,
+        columns: Vec::new(),
+        report: TestReport::default(),
+    };
+    run.execute(commands, dir)?;
+
+    let mut report = run.report;
+    if let Some(file) = &report.output_file {
+        fs::write(dir.join(file), &report.output)?;
+    }
+    if let Some(file) = &report.compare_file {
+        let expected = fs::read_to_string(dir.join(file))?;
+        report.mismatches = compare(&report.output, &expected);
+    }
+
+    Ok(report)
+}
+
+/// Compares the output line by line, trailing whitespace is ignored.
+pub fn compare(output: &str, expected: &str) -> Vec<Mismatch> {
+    let mut expected = expected.lines();
+
+    output
+        .lines()
+        .enumerate()
+        .filter_map(|(index, actual)| {
+            let expected = expected.next().map(|line| line.trim_end().to_string());
+            match expected {
+                Some(ref line) if line == actual.trim_end() => None,
+                _ => Some(Mismatch { line: index + 1, expected, actual: actual.to_string() }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    const MULT_TST: &str = "\
+// This file is part of www.nand2tetris.org
+
+load Mult.asm,
+output-file Mult.out,
+compare-to Mult.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+set RAM[0] 0,   // Set test arguments
+set RAM[1] 0,
+set RAM[2] -1;  // Test that program initialized product to 0
+repeat 20 {
+  ticktock;
+}
+set RAM[0] 0,   // Restore arguments in case program used them as loop counter
+set RAM[1] 0,
+output;
+
+set PC 0,
+set RAM[0] 3,   /* Set test arguments */
+set RAM[1] 1,
+set RAM[2] -1;
+repeat 50 {
+  ticktock;
+}
+set RAM[0] 3,
+set RAM[1] 1,
+output;
+";
+
+    const MULT_ASM: &str = "\
+@R2
+M=0
+(LOOP)
+@R1
+D=M
+@END
+D;JLE
+@R0
+D=M
+@R2
+M=D+M
+@R1
+M=M-1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+    #[test]
+    fn test_parse_script() {
+        let commands = parse_script(MULT_TST).unwrap();
+
+        assert_eq!(commands.len(), 19);
+        assert_eq!(commands[0], Command::Load("Mult.asm".to_string()));
+        assert_eq!(
+            commands[3],
+            Command::OutputList(
+                (0..3)
+                    .map(|address| Column {
+                        name: format!("RAM[{}]", address),
+                        target: Target::Ram(address),
+                        format: 'D',
+                        pad_left: 2,
+                        width: 6,
+                        pad_right: 2,
+                    })
+                    .collect()
+            )
+        );
+        assert_eq!(commands[6], Command::Set(Target::Ram(2), 0xffff));
+        assert_eq!(commands[7], Command::Repeat(20, vec![Command::TickTock]));
+        assert_eq!(commands[10], Command::Output);
+        assert_eq!(commands[11], Command::Set(Target::PC, 0));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |script| parse_script(script).unwrap_err();
+
+        assert_eq!(error("load A.asm,\n\nset RAM[0]").line, 3);
+        assert_eq!(error("repeat x { ticktock; }").message, "bad repeat count `x`");
+        assert_eq!(error("repeat 2 { ticktock;").message, "missing `}` after repeat");
+        assert_eq!(error("ticktock; }").message, "unexpected `}`");
+        assert_eq!(error("set time 3;").message, "can't set `time`");
+        assert_eq!(error("output-list RAM[0]%Q1.6.1;").message, "bad output column `RAM[0]%Q1.6.1`");
+        assert_eq!(error("while RAM[0] <> 0 { ticktock; }").line, 1);
+    }
+
+    #[test]
+    fn test_values_and_columns() {
+        assert_eq!(parse_value("%XFF"), Some(255));
+        assert_eq!(parse_value("%B101"), Some(5));
+        assert_eq!(parse_value("%D-2"), Some(0xfffe));
+        assert_eq!(parse_value("40000"), None);
+
+        let column = parse_column("D%B1.16.1").unwrap();
+        assert_eq!(format_value(5, &column), "0000000000000101");
+        let column = parse_column("A%X1.2.1").unwrap();
+        assert_eq!(format_value(0x1234, &column), "34");
+        let column = parse_column("time").unwrap();
+        assert_eq!(format_value(0xffff, &column), "    -1");
+    }
+
+    #[test]
+    fn test_run_script() {
+        let dir = TempDir::new("test_run_script");
+        fs::write(dir.join("Mult.asm"), MULT_ASM).unwrap();
+        fs::write(
+            dir.join("Mult.cmp"),
+            "|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n|       0  |       0  |       0  |\n|       3  |       1  |       3  |\n",
+        )
+        .unwrap();
+
+        let report = run_script(&parse_script(MULT_TST).unwrap(), &dir).unwrap();
+        assert_eq!(report.mismatches, vec![]);
+        assert_eq!(fs::read_to_string(dir.join("Mult.out")).unwrap(), report.output);
+
+        fs::write(
+            dir.join("Mult.cmp"),
+            "|  RAM[0]  |  RAM[1]  |  RAM[2]  |\n|       0  |       0  |       0  |\n|       3  |       1  |       4  |\n",
+        )
+        .unwrap();
+        let report = run_script(&parse_script(MULT_TST).unwrap(), &dir).unwrap();
+        assert_eq!(
+            report.mismatches,
+            vec![Mismatch {
+                line: 3,
+                expected: Some("|       3  |       1  |       4  |".to_string()),
+                actual: "|       3  |       1  |       3  |".to_string(),
+            }]
+        );
+    }
+}